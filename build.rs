@@ -22,11 +22,14 @@ fn main() {
 
     // actually copy the resources folder
     let out_dir = get_output_path();
-    Command::new("cp")
+    let status = Command::new("cp")
         .arg("-r")
         .arg("resources")
         .arg(&out_dir)
-        .spawn()
+        .status()
         .expect("failed to spawn copy process");
+    if !status.success() {
+        panic!("copying the resources to {} failed: {}", out_dir.display(), status);
+    }
 }
 
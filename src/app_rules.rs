@@ -0,0 +1,162 @@
+// switches profiles automatically while certain applications are running.
+// the rules live in the config, e.g.
+/*
+* "app_rules": [ { "matches": [ "reaper", "bitwig-studio" ], "profile": "Recording" } ]
+*/
+// the applications are followed with pw-dump --monitor, which prints a JSON array
+// with the new and changed objects for every change of the graph. removed objects
+// are reported as { "id": 78, "info": null }.
+
+use std::collections::HashMap;
+
+use iced::futures::{future, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::process_stream::{self, Output};
+
+/// Applies profile while any application matching one of matches runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppRule {
+    /// application names or binaries, compared case insensitively
+    pub matches: Vec<String>,
+    /// name of the LateProfile to apply
+    pub profile: String,
+}
+
+/// An application connected to pipewire
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct App {
+    /// application.name
+    pub name: Option<String>,
+    /// application.process.binary
+    pub binary: Option<String>,
+}
+
+impl AppRule {
+    pub fn matches(&self, app: &App) -> bool {
+        let is = |value: &Option<String>, entry: &str| value.as_ref()
+            .is_some_and(|v| v.eq_ignore_ascii_case(entry));
+        self.matches.iter()
+            .map(|entry| entry.trim())
+            .any(|entry| is(&app.name, entry) || is(&app.binary, entry))
+    }
+}
+
+/// @returns the first rule matching any of apps
+pub fn active_rule<'a>(rules: &'a [AppRule], apps: &[App]) -> Option<&'a AppRule> {
+    rules.iter().find(|rule| apps.iter().any(|app| rule.matches(app)))
+}
+
+/// keeps track of the applications across the updates of pw-dump --monitor
+#[derive(Debug, Default)]
+pub struct AppTracker {
+    /// the lines of the update being read
+    buffer: String,
+    /// applications by the id of the object (client or node) they were seen on
+    apps: HashMap<u32, App>,
+}
+
+impl AppTracker {
+    /// @returns all applications, once an update is complete
+    pub fn push(&mut self, line: &str) -> Option<Vec<App>> {
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+        // every update is a pretty printed array, closed by a "]" on its own line
+        if line != "]" {
+            return None;
+        }
+        let update = std::mem::take(&mut self.buffer);
+        let objects: Vec<Value> = serde_json::from_str(&update).ok()?;
+        for object in &objects {
+            self.apply(object);
+        }
+        Some(self.apps())
+    }
+
+    fn apply(&mut self, object: &Value) {
+        let Some(id) = object["id"].as_u64().and_then(|id| u32::try_from(id).ok()) else {
+            return;
+        };
+        if object["info"].is_null() {
+            self.apps.remove(&id);
+            return;
+        }
+        let props = &object["info"]["props"];
+        if !props.is_object() {
+            // only the state changed, the application stays the same
+            return;
+        }
+        let prop = |key: &str| props[key].as_str().map(str::to_string);
+        let app = App { name: prop("application.name"), binary: prop("application.process.binary") };
+        if app.name.is_some() || app.binary.is_some() {
+            self.apps.insert(id, app);
+        } else {
+            self.apps.remove(&id);
+        }
+    }
+
+    /// @returns the applications, without duplicates
+    pub fn apps(&self) -> Vec<App> {
+        let mut apps: Vec<App> = self.apps.values().cloned().collect();
+        apps.sort();
+        apps.dedup();
+        apps
+    }
+}
+
+/// @returns a stream of the running applications, updated whenever the graph changes
+pub fn watch_apps() -> impl Stream<Item = Vec<App>> {
+    let mut tracker = AppTracker::default();
+    let mut previous = None;
+    process_stream::lines("pw-dump", &["--monitor"])
+        .filter_map(move |output| {
+            let line = match output {
+                Output::Line { line, .. } => line,
+                // pw-dump reports every object again once it is restarted
+                Output::Stopped(_) => {
+                    tracker = AppTracker::default();
+                    return future::ready(None);
+                }
+            };
+            let apps = tracker.push(&line)
+                .filter(|apps| previous.as_ref() != Some(apps));
+            if apps.is_some() {
+                previous = apps.clone();
+            }
+            future::ready(apps)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MONITOR: &str = include_str!("../tests/fixtures/pw-dump/monitor.txt");
+
+    fn app(name: &str, binary: &str) -> App {
+        App { name: Some(name.to_string()), binary: Some(binary.to_string()) }
+    }
+
+    #[test]
+    fn tracks_apps_across_updates() {
+        let mut tracker = AppTracker::default();
+        let updates: Vec<Vec<App>> = MONITOR.lines().filter_map(|l| tracker.push(l)).collect();
+        assert_eq!(updates, vec![
+            vec![app("Firefox", "firefox")],
+            vec![app("Firefox", "firefox"), app("REAPER", "reaper")],
+            vec![app("Firefox", "firefox")],
+        ]);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            AppRule { matches: vec!["bitwig-studio".to_string()], profile: "Mixing".to_string() },
+            AppRule { matches: vec!["Reaper".to_string()], profile: "Recording".to_string() },
+        ];
+        let apps = vec![app("Firefox", "firefox"), app("REAPER", "reaper")];
+        assert_eq!(active_rule(&rules, &apps).map(|r| r.profile.as_str()), Some("Recording"));
+        assert_eq!(active_rule(&rules, &apps[..1]), None);
+    }
+}
@@ -0,0 +1,229 @@
+// following the light/dark preference of the desktop.
+// the preference is read from the XDG desktop portal, setting color-scheme of the namespace
+// org.freedesktop.appearance: 0 is no preference, 1 prefers dark and 2 prefers light, see
+// https://flatpak.github.io/xdg-desktop-portal/docs/doc-org.freedesktop.portal.Settings.html
+// changes arrive as SettingChanged signals, which are listened to on their own thread.
+
+use std::thread;
+use std::time::Duration;
+
+use iced::futures::channel::mpsc;
+use iced::futures::{SinkExt, Stream, StreamExt};
+use iced::Theme;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::config::LateConfig;
+
+static PORTAL_NAME: &str = "org.freedesktop.portal.Desktop";
+static PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+static SETTINGS_INTERFACE: &str = "org.freedesktop.portal.Settings";
+static APPEARANCE_NAMESPACE: &str = "org.freedesktop.appearance";
+static COLOR_SCHEME_KEY: &str = "color-scheme";
+
+/// What the desktop prefers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorScheme {
+    /// no preference, or no portal to ask
+    #[default]
+    NoPreference,
+    Dark,
+    Light,
+}
+
+impl From<u32> for ColorScheme {
+    fn from(value: u32) -> Self {
+        // unknown values are to be treated as no preference
+        match value {
+            1 => ColorScheme::Dark,
+            2 => ColorScheme::Light,
+            _ => ColorScheme::NoPreference,
+        }
+    }
+}
+
+impl ColorScheme {
+    /// Read wraps the value in another variant, ReadOne and SettingChanged don't
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::U32(scheme) => Some(ColorScheme::from(*scheme)),
+            Value::Value(inner) => ColorScheme::from_value(inner),
+            _ => None,
+        }
+    }
+}
+
+/// @returns the theme to show: the light or dark theme if late follows the desktop, otherwise the chosen one.
+/// without a preference, the light theme is used, as the portal spec suggests
+pub fn theme_for(config: &LateConfig, scheme: ColorScheme) -> Theme {
+    if !config.follow_system_theme {
+        return config.theme.clone();
+    }
+    match scheme {
+        ColorScheme::Dark => config.dark_theme.clone(),
+        ColorScheme::Light | ColorScheme::NoPreference => config.light_theme.clone(),
+    }
+}
+
+fn settings_proxy(connection: &Connection) -> zbus::Result<Proxy<'static>> {
+    Proxy::new(connection, PORTAL_NAME, PORTAL_PATH, SETTINGS_INTERFACE)
+}
+
+/// asks the portal for the current color scheme
+fn read_color_scheme(settings: &Proxy) -> zbus::Result<ColorScheme> {
+    // Read is deprecated in favor of ReadOne, but older portals only have Read
+    let value: OwnedValue = settings.call("Read", &(APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY))?;
+    Ok(ColorScheme::from_value(&value).unwrap_or_default())
+}
+
+/// A color scheme, or why it can't be read
+pub type SchemeEvent = Result<ColorScheme, String>;
+
+/// @returns a stream of the color scheme the desktop prefers, starting with the current one
+pub fn watch() -> impl Stream<Item = SchemeEvent> {
+    iced::stream::channel(10, |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            let listened = Connection::session().and_then(|connection| {
+                close_when_unused(&connection, sender.clone());
+                listen(&connection, &sender)
+            });
+            if let Err(e) = listened {
+                // after the connection was closed, nobody gets this anymore
+                let _ = sender.unbounded_send(Err(format!(
+                    "Could not read the color scheme of the desktop: {e}"
+                )));
+            }
+        });
+
+        while let Some(scheme) = receiver.next().await {
+            if output.send(scheme).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// closes connection once nobody listens to sender anymore, which ends waiting for the next signal
+fn close_when_unused(connection: &Connection, sender: mpsc::UnboundedSender<SchemeEvent>) {
+    let connection = connection.clone();
+    thread::spawn(move || {
+        while !sender.is_closed() {
+            thread::sleep(Duration::from_secs(1));
+        }
+        let _ = connection.close();
+    });
+}
+
+/// sends the current color scheme and every change of it, until nobody listens anymore
+fn listen(
+    connection: &Connection,
+    sender: &mpsc::UnboundedSender<SchemeEvent>,
+) -> zbus::Result<()> {
+    let settings = settings_proxy(connection)?;
+    // subscribe first, so a change right after reading isn't missed
+    let changes = settings.receive_signal("SettingChanged")?;
+    if sender
+        .unbounded_send(Ok(read_color_scheme(&settings)?))
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    for message in changes {
+        let Ok((namespace, key, value)) =
+            message.body().deserialize::<(String, String, OwnedValue)>()
+        else {
+            continue;
+        };
+        if namespace != APPEARANCE_NAMESPACE || key != COLOR_SCHEME_KEY {
+            continue;
+        }
+        let Some(scheme) = ColorScheme::from_value(&value) else {
+            continue;
+        };
+        if sender.unbounded_send(Ok(scheme)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus_service::tests::PrivateBus;
+    use iced::futures::executor;
+    use zbus::blocking::connection;
+
+    #[test]
+    fn picks_theme_for_color_scheme() {
+        let mut config = LateConfig {
+            theme: Theme::Nord,
+            ..Default::default()
+        };
+        assert_eq!(theme_for(&config, ColorScheme::Dark), Theme::Nord);
+
+        config.follow_system_theme = true;
+        assert_eq!(theme_for(&config, ColorScheme::Dark), config.dark_theme);
+        assert_eq!(theme_for(&config, ColorScheme::Light), config.light_theme);
+        assert_eq!(
+            theme_for(&config, ColorScheme::NoPreference),
+            config.light_theme
+        );
+    }
+
+    /// answers Read like the portal does, with the value wrapped in two variants
+    struct FakePortal;
+
+    #[zbus::interface(name = "org.freedesktop.portal.Settings")]
+    impl FakePortal {
+        fn read(&self, _namespace: &str, _key: &str) -> OwnedValue {
+            Value::Value(Box::new(Value::U32(2))).try_into().unwrap()
+        }
+    }
+
+    #[test]
+    fn follows_the_portal() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("dbus-daemon not available, skipping");
+            return;
+        };
+        let portal = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .name(PORTAL_NAME)
+            .unwrap()
+            .serve_at(PORTAL_PATH, FakePortal)
+            .unwrap()
+            .build()
+            .unwrap();
+        let client = connection::Builder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap();
+        let (sender, receiver) = mpsc::unbounded();
+        close_when_unused(&client, sender.clone());
+        let listener = thread::spawn(move || listen(&client, &sender));
+
+        let mut schemes = executor::block_on_stream(receiver);
+        assert_eq!(schemes.next(), Some(Ok(ColorScheme::Light)));
+        let emit = |namespace: &str, scheme: u32| {
+            portal
+                .emit_signal(
+                    None::<()>,
+                    PORTAL_PATH,
+                    SETTINGS_INTERFACE,
+                    "SettingChanged",
+                    &(namespace, COLOR_SCHEME_KEY, Value::U32(scheme)),
+                )
+                .unwrap()
+        };
+        emit("org.gnome.desktop.interface", 2);
+        emit(APPEARANCE_NAMESPACE, 1);
+        assert_eq!(schemes.next(), Some(Ok(ColorScheme::Dark)));
+
+        // without anybody listening, the thread ends without another signal
+        drop(schemes);
+        listener.join().unwrap().unwrap();
+    }
+}
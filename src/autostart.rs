@@ -0,0 +1,200 @@
+// applying a profile when the user logs in.
+// `late --apply-startup` applies the startup profile from the config, or the default
+// profile if none is chosen. it is started by an XDG autostart entry
+// (~/.config/autostart/late.desktop) or by a systemd user unit
+// (~/.config/systemd/user/late-startup.service).
+// pipewire may start after either of them, so late waits until pw-metadata answers.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::LateConfig;
+use crate::profile::LateProfile;
+use crate::settings_backend::{SettingsBackend, QUANTUM_KEY};
+use crate::storage;
+
+static AUTOSTART_DIR: &str = "autostart";
+static DESKTOP_NAME: &str = "late.desktop";
+static SYSTEMD_DIR: &str = "systemd/user";
+static UNIT_NAME: &str = "late-startup.service";
+
+/// how often and how long to wait for pipewire at login
+pub const PIPEWIRE_ATTEMPTS: u32 = 60;
+pub const PIPEWIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What starts `late --apply-startup` at login
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutostartKind {
+    /// an XDG autostart entry, run by the desktop session
+    Desktop,
+    /// a systemd user unit, ordered after pipewire.service
+    Systemd,
+}
+
+impl AutostartKind {
+    pub const ALL: [AutostartKind; 2] = [AutostartKind::Desktop, AutostartKind::Systemd];
+
+    /// @returns where the file starting late is written to
+    pub fn path(&self) -> io::Result<PathBuf> {
+        let mut path = storage::xdg_config_home().map_err(io::Error::other)?;
+        match self {
+            AutostartKind::Desktop => path.extend([AUTOSTART_DIR, DESKTOP_NAME]),
+            AutostartKind::Systemd => path.extend([SYSTEMD_DIR, UNIT_NAME]),
+        }
+        Ok(path)
+    }
+
+    /// @returns the content of the file, starting exe with --apply-startup
+    /// (and --config-dir, if late doesn't use the default one)
+    pub fn render(&self, exe: &Path, config_dir: Option<&Path>) -> String {
+        // both formats split the command line at spaces, unless quoted
+        let quote = |path: &Path| {
+            let path = path.display().to_string();
+            if path.contains(' ') { format!("\"{}\"", path) } else { path }
+        };
+        let exe = match config_dir {
+            Some(dir) => format!("{} --config-dir {}", quote(exe), quote(dir)),
+            None => quote(exe),
+        };
+        match self {
+            AutostartKind::Desktop => format!("\
+[Desktop Entry]
+Type=Application
+Name=Late
+Comment=Apply the startup profile
+Exec={exe} --apply-startup
+Terminal=false
+NoDisplay=true
+X-GNOME-Autostart-enabled=true
+"),
+            AutostartKind::Systemd => format!("\
+[Unit]
+Description=Apply the Late startup profile
+Wants=pipewire.service
+After=pipewire.service
+
+[Service]
+Type=oneshot
+ExecStart={exe} --apply-startup
+
+[Install]
+WantedBy=default.target
+"),
+        }
+    }
+}
+
+/// runs systemctl --user with args
+fn systemctl(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("systemctl").arg("--user").args(args).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("systemctl --user {}: {}", args.join(" "), status)));
+    }
+    Ok(())
+}
+
+/// writes the file starting late at login and enables it
+/// @returns the path of the file
+pub fn install(kind: AutostartKind) -> io::Result<PathBuf> {
+    let path = kind.path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, kind.render(&std::env::current_exe()?, storage::config_dir_override()))?;
+    if kind == AutostartKind::Systemd {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", UNIT_NAME])?;
+    }
+    Ok(path)
+}
+
+/// removes whatever starts late at login
+/// @returns the removed files
+pub fn remove() -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for kind in AutostartKind::ALL {
+        let path = kind.path()?;
+        if !path.exists() {
+            continue;
+        }
+        if kind == AutostartKind::Systemd {
+            systemctl(&["disable", UNIT_NAME])?;
+        }
+        fs::remove_file(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// @returns how late is started at login, if it is
+pub fn installed() -> Option<AutostartKind> {
+    AutostartKind::ALL.into_iter()
+        .find(|kind| kind.path().is_ok_and(|path| path.exists()))
+}
+
+/// @returns the profile to apply at login: the one chosen in the config, otherwise the default one
+pub fn startup_profile(config: &LateConfig, profiles: &[LateProfile]) -> Option<LateProfile> {
+    match &config.startup_profile {
+        Some(name) => profiles.iter().find(|profile| &profile.name == name),
+        None => profiles.iter().find(|profile| profile.is_default),
+    }.cloned()
+}
+
+/// waits until pipewire answers, trying up to attempts times
+/// @returns the last error, if pipewire never answered
+pub fn wait_for_pipewire(backend: &dyn SettingsBackend, attempts: u32, interval: Duration) -> io::Result<()> {
+    let mut last = io::Error::other("pipewire was not asked");
+    for attempt in 0..attempts {
+        if attempt > 0 {
+            thread::sleep(interval);
+        }
+        // clock.quantum is set as soon as the settings metadata exists
+        match backend.get(QUANTUM_KEY) {
+            Ok(Some(_)) => return Ok(()),
+            Ok(None) => last = io::Error::other("the settings metadata is not there yet"),
+            Err(e) => last = io::Error::other(e),
+        }
+    }
+    Err(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings_backend::MemoryBackend;
+
+    fn profile(name: &str, is_default: bool) -> LateProfile {
+        LateProfile { name: name.to_string(), is_default, ..Default::default() }
+    }
+
+    #[test]
+    fn chooses_configured_or_default_profile() {
+        let profiles = vec![profile("Recording", false), profile("Mixing", true)];
+        let mut config = LateConfig::default();
+        assert_eq!(startup_profile(&config, &profiles).map(|p| p.name), Some("Mixing".to_string()));
+        config.startup_profile = Some("Recording".to_string());
+        assert_eq!(startup_profile(&config, &profiles).map(|p| p.name), Some("Recording".to_string()));
+        config.startup_profile = Some("Gone".to_string());
+        assert!(startup_profile(&config, &profiles).is_none());
+    }
+
+    #[test]
+    fn renders_quoted_exec_lines() {
+        let exe = Path::new("/opt/my apps/late");
+        assert!(AutostartKind::Desktop.render(exe, None).contains("Exec=\"/opt/my apps/late\" --apply-startup\n"));
+        assert!(AutostartKind::Systemd.render(Path::new("/usr/bin/late"), Some(Path::new("/srv/late")))
+            .contains("ExecStart=/usr/bin/late --config-dir /srv/late --apply-startup\n"));
+    }
+
+    #[test]
+    fn waits_for_the_settings_metadata() {
+        let backend = MemoryBackend::default();
+        assert!(wait_for_pipewire(&backend, 2, Duration::ZERO).is_err());
+        backend.set(QUANTUM_KEY, "1024").unwrap();
+        assert!(wait_for_pipewire(&backend, 2, Duration::ZERO).is_ok());
+    }
+}
@@ -1,5 +1,7 @@
 
-use std::process::{Command, Stdio};
+use crate::error::Result;
+use crate::hardware::{self, HardwareLimits, LimitError};
+use crate::settings_backend::{SettingsBackend, FORCE_QUANTUM_KEY, QUANTUM_KEY, MIN_QUANTUM_KEY, MAX_QUANTUM_KEY};
 
 pub fn get_available_buffer_sizes() -> Vec<u32> {
     vec![
@@ -13,42 +15,82 @@ pub fn get_available_buffer_sizes() -> Vec<u32> {
     ]
 }
 
-pub fn get_current_buffer_size() -> Option<u32> {
-    // fetch the current sample rate by terminal command
-    let cmd_str = "pw-metadata -n settings 0 clock.force-quantum";
+/// @returns the reset and the given buffer sizes (e.g. the favourites) within the quantum limits of pipewire
+pub fn get_supported_buffer_sizes(limits: &HardwareLimits, sizes: &[u32]) -> Vec<u32> {
+    let mut sizes: Vec<u32> = std::iter::once(0)
+        .chain(sizes.iter().copied())
+        .filter(|size| limits.supports_quantum(*size))
+        .collect();
+    sizes.sort_unstable();
+    sizes.dedup();
+    sizes
+}
+
+/// parses a typed buffer size and checks it against the limits
+pub fn parse_buffer_size(text: &str, limits: &HardwareLimits) -> std::result::Result<u32, LimitError> {
+    let size = hardware::parse_value(text)?;
+    limits.check_quantum(size)?;
+    Ok(size)
+}
+
+/// @returns the forced buffer size, None if none is forced
+pub fn get_current_buffer_size(backend: &dyn SettingsBackend) -> Result<Option<u32>> {
+    // a value pipewire can't make sense of either is treated as not forced
+    Ok(backend.get(FORCE_QUANTUM_KEY)?.and_then(|value| value.parse().ok()))
+}
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .stdout(Stdio::piped())
-        .arg(cmd_str)
-        .output()
-        .unwrap();
+/// @returns the quantum pipewire uses when no buffer size is forced
+pub fn get_default_buffer_size(backend: &dyn SettingsBackend) -> Result<Option<u32>> {
+    Ok(backend.get(QUANTUM_KEY)?.and_then(|value| value.parse().ok()))
+}
 
-    // the response lookse something like this
-    /*
-    * Found "settings" metadata 31
-    * update: id:0 key:'clock.force-quantum' value:'128' type:''
-    */
-    let cmd_return_str = String::from_utf8(output.stdout).unwrap();
-    // first remove everything until "value:'"
-    let sub1 = &cmd_return_str[cmd_return_str.find("value:'").unwrap_or(0)..];
-    // now remove the "value:'" itself
-    let sub2 = &sub1["value:'".len()..];
-    // lastly, remove everything after the (now) first "'",
-    // as that concludes the actual value
-    let sub3 = &sub2[0..sub2.find("'").unwrap_or(sub2.len())];
+pub fn set_buffer_size(backend: &dyn SettingsBackend, size: u32) -> Result<()> {
+    set_or_reset(backend, FORCE_QUANTUM_KEY, size)
+}
 
-    // turn it into the option for the combo box
-    sub3.parse().ok()
+/// @returns clock.min-quantum and clock.max-quantum
+pub fn get_quantum_limits(backend: &dyn SettingsBackend) -> Result<(Option<u32>, Option<u32>)> {
+    let get = |key| -> Result<Option<u32>> { Ok(backend.get(key)?.and_then(|value| value.parse().ok())) };
+    Ok((get(MIN_QUANTUM_KEY)?, get(MAX_QUANTUM_KEY)?))
 }
 
-pub fn set_buffer_size(size: u32) {
-    let cmd = format!("pw-metadata -n settings 0 clock.force-quantum {}", size);
+/// checks that min is not above max. None means not set
+pub fn check_quantum_limits(min: Option<u32>, max: Option<u32>) -> std::result::Result<(), LimitError> {
+    match (min, max) {
+        (Some(min), Some(max)) if min > max => Err(LimitError::MinAboveMax { min, max }),
+        _ => Ok(()),
+    }
+}
 
-    Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .output()
-        .expect("");
+/// sets the smallest and largest quantum clients may ask for, checked as pair against each other.
+/// None keeps the current value, 0 acts as a reset.
+/// the two are written in the order which keeps min below max in between, i.e. max first when the
+/// min is raised, so that pipewire never sees a min above the max
+pub fn set_quantum_limits(backend: &dyn SettingsBackend, min: Option<u32>, max: Option<u32>) -> Result<()> {
+    if min.is_none() && max.is_none() {
+        return Ok(());
+    }
+    let (current_min, current_max) = get_quantum_limits(backend)?;
+    let set = |size: Option<u32>| size.map(|size| Some(size).filter(|s| *s != 0));
+    let new_min = set(min).unwrap_or(current_min);
+    let new_max = set(max).unwrap_or(current_max);
+    check_quantum_limits(new_min, new_max)?;
+
+    let write_min = || min.map_or(Ok(()), |size| set_or_reset(backend, MIN_QUANTUM_KEY, size));
+    let write_max = || max.map_or(Ok(()), |size| set_or_reset(backend, MAX_QUANTUM_KEY, size));
+    if new_min > current_min {
+        write_max()?;
+        write_min()
+    } else {
+        write_min()?;
+        write_max()
+    }
 }
 
+fn set_or_reset(backend: &dyn SettingsBackend, key: &str, size: u32) -> Result<()> {
+    if size == 0 {
+        backend.reset(key)
+    } else {
+        backend.set(key, &size.to_string())
+    }
+}
@@ -0,0 +1,514 @@
+
+// headless command line interface.
+// allows switching settings from scripts or keyboard shortcuts without opening the GUI.
+// any command line argument makes late run in this mode instead of opening the window.
+
+use std::path::PathBuf;
+
+use crate::autostart::{self, AutostartKind};
+use crate::buffer_size;
+use crate::config;
+use crate::dbus_service;
+use crate::hardware;
+use crate::persist;
+use crate::sample_rate;
+use crate::profile::{self, LateProfile};
+use crate::profile_exchange::{self, OnConflict};
+use crate::settings_backend::SettingsBackend;
+
+static USAGE: &str = "\
+Usage: late [--config-dir <DIR>] [COMMAND]
+
+Without a command, the GUI is opened. Config and profiles are kept in
+$XDG_CONFIG_HOME/late (~/.config/late), or in DIR if given.
+
+Commands:
+  get                                  print the current buffer size and sample rate
+  set <VALUES>                         set the given values (0 or an empty list resets)
+  reset                                reset buffer size and sample rate to the pipewire defaults
+  profile list                         list all saved profiles
+  profile apply <NAME>                 apply a saved profile
+  profile save <NAME> [VALUES]         save a profile. buffer size and sample rate are
+                                       taken from the current values if not given
+  profile delete <NAME>                delete a saved profile
+  profile export <FILE> [NAME...]      export the given (or all) profiles to a .json
+                                       or .toml file
+  profile import <FILE> [--on-conflict rename|overwrite|skip]
+                                       import profiles from a file. profiles with a name
+                                       which is taken already are renamed by default
+  persist <NAME> [--yes]               keep a profile across pipewire restarts by writing
+                                       a pipewire.conf.d drop-in. prints the changes,
+                                       only writes with --yes
+  persist --remove [--yes]             remove the drop-in again
+  autostart [--systemd] [NAME]         apply NAME (or the default profile) at login,
+                                       through ~/.config/autostart or a systemd user unit
+  autostart --remove                   don't apply anything at login anymore
+  serve                                provide the org.late.Settings D-Bus interface
+                                       on the session bus until stopped
+  help                                 print this help
+  --daemon                             start the GUI hidden, with a tray icon
+  --apply-startup                      wait for pipewire, then apply the startup profile
+
+Values:
+  -q, --quantum <N>                    forced buffer size (clock.force-quantum)
+  -r, --rate <N>                       forced sample rate (clock.force-rate)
+  --min-quantum <N>                    clock.min-quantum
+  --max-quantum <N>                    clock.max-quantum
+  --allowed-rates <N,N,...>            clock.allowed-rates";
+
+/// The values given on the command line. None means "not given"
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ClockValues {
+    pub quantum: Option<u32>,
+    pub rate: Option<u32>,
+    pub min_quantum: Option<u32>,
+    pub max_quantum: Option<u32>,
+    pub allowed_rates: Option<Vec<u32>>,
+}
+
+impl ClockValues {
+    fn is_empty(&self) -> bool {
+        *self == ClockValues::default()
+    }
+}
+
+/// A command given on the command line
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliCommand {
+    Help,
+    Get,
+    Set(ClockValues),
+    Reset,
+    ProfileList,
+    ProfileApply(String),
+    ProfileSave { name: String, values: ClockValues },
+    ProfileDelete(String),
+    /// no names exports all profiles
+    ProfileExport { file: String, names: Vec<String> },
+    ProfileImport { file: String, on_conflict: OnConflict },
+    /// None removes the drop-in
+    Persist { profile: Option<String>, confirmed: bool },
+    /// runs the D-Bus interface
+    Serve,
+    /// None stops applying a profile at login. a profile name of None applies the default profile
+    Autostart { kind: Option<AutostartKind>, profile: Option<String> },
+    ApplyStartup,
+}
+
+/// takes --config-dir <DIR> off the front of args, it applies to the GUI and all commands
+/// @returns DIR, if given
+pub fn take_config_dir(args: &mut Vec<String>) -> Option<PathBuf> {
+    if args.len() < 2 || args[0] != "--config-dir" {
+        return None;
+    }
+    // the dir ends up in autostart entries, which don't run where late was started
+    args.drain(..2).nth(1).map(|dir| std::path::absolute(&dir).unwrap_or(PathBuf::from(dir)))
+}
+
+/// parses the command line arguments (without the program name)
+pub fn parse_args(args: &[String]) -> Result<CliCommand, String> {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    match args.as_slice() {
+        [] | ["help"] | ["--help"] | ["-h"] => Ok(CliCommand::Help),
+        ["get"] => Ok(CliCommand::Get),
+        ["set", options @ ..] => {
+            let values = parse_values(options)?;
+            if values.is_empty() {
+                return Err("set needs at least one value".to_string());
+            }
+            Ok(CliCommand::Set(values))
+        }
+        ["reset"] => Ok(CliCommand::Reset),
+        ["profile", "list"] => Ok(CliCommand::ProfileList),
+        ["profile", "apply", name] => Ok(CliCommand::ProfileApply(name.to_string())),
+        ["profile", "save", name, options @ ..] => {
+            let values = parse_values(options)?;
+            Ok(CliCommand::ProfileSave { name: name.to_string(), values })
+        }
+        ["profile", "delete", name] => Ok(CliCommand::ProfileDelete(name.to_string())),
+        ["profile", "export", file, names @ ..] => Ok(CliCommand::ProfileExport {
+            file: file.to_string(),
+            names: names.iter().map(|n| n.to_string()).collect(),
+        }),
+        ["profile", "import", file] =>
+            Ok(CliCommand::ProfileImport { file: file.to_string(), on_conflict: OnConflict::default() }),
+        ["profile", "import", file, "--on-conflict", mode] => Ok(CliCommand::ProfileImport {
+            file: file.to_string(),
+            on_conflict: OnConflict::parse(mode).ok_or(format!("unknown conflict handling '{}'", mode))?,
+        }),
+        ["persist", "--remove"] => Ok(CliCommand::Persist { profile: None, confirmed: false }),
+        ["persist", "--remove", "--yes"] => Ok(CliCommand::Persist { profile: None, confirmed: true }),
+        ["persist", name] if !name.starts_with('-') =>
+            Ok(CliCommand::Persist { profile: Some(name.to_string()), confirmed: false }),
+        ["persist", name, "--yes"] if !name.starts_with('-') =>
+            Ok(CliCommand::Persist { profile: Some(name.to_string()), confirmed: true }),
+        ["serve"] => Ok(CliCommand::Serve),
+        ["autostart", "--remove"] => Ok(CliCommand::Autostart { kind: None, profile: None }),
+        ["autostart", options @ ..] => {
+            let (kind, name) = match options {
+                ["--systemd", name @ ..] => (AutostartKind::Systemd, name),
+                name => (AutostartKind::Desktop, name),
+            };
+            match name {
+                [] => Ok(CliCommand::Autostart { kind: Some(kind), profile: None }),
+                [name] if !name.starts_with('-') =>
+                    Ok(CliCommand::Autostart { kind: Some(kind), profile: Some(name.to_string()) }),
+                _ => Err(format!("unknown command '{}'", args.join(" "))),
+            }
+        }
+        ["--apply-startup"] => Ok(CliCommand::ApplyStartup),
+        _ => Err(format!("unknown command '{}'", args.join(" "))),
+    }
+}
+
+/// parses the values options in any order
+fn parse_values(options: &[&str]) -> Result<ClockValues, String> {
+    let mut values = ClockValues::default();
+    let mut iter = options.iter();
+    while let Some(option) = iter.next() {
+        let value = iter.next()
+            .ok_or(format!("{} needs a value", option))?;
+        let invalid = || format!("invalid value '{}' for {}", value, option);
+        let number = || value.parse::<u32>().map_err(|_| invalid());
+        match *option {
+            "--quantum" | "-q" => values.quantum = Some(number()?),
+            "--rate" | "-r" => values.rate = Some(number()?),
+            "--min-quantum" => values.min_quantum = Some(number()?),
+            "--max-quantum" => values.max_quantum = Some(number()?),
+            "--allowed-rates" => {
+                let rates: Result<Vec<u32>, _> = value.split(',')
+                    .filter(|r| !r.trim().is_empty())
+                    .map(|r| r.trim().parse())
+                    .collect();
+                values.allowed_rates = Some(rates.map_err(|_| invalid())?);
+            }
+            _ => return Err(format!("unknown option '{}'", option)),
+        }
+    }
+    Ok(values)
+}
+
+/// parses and executes the command line
+/// @returns the exit code of the process
+pub fn run(args: &[String], backend: &dyn SettingsBackend) -> i32 {
+    let command = match parse_args(args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+
+    match execute(command, backend) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+fn execute(command: CliCommand, backend: &dyn SettingsBackend) -> Result<(), String> {
+    match command {
+        CliCommand::Help => {
+            println!("{}", USAGE);
+        }
+        CliCommand::Get => {
+            let quantum = buffer_size::get_current_buffer_size(backend)
+                .map_err(|e| format!("error reading buffer size: {e}"))?;
+            let rate = sample_rate::get_current_sample_rate(backend)
+                .map_err(|e| format!("error reading sample rate: {e}"))?;
+            println!("buffer size: {}", value_or_unset(quantum));
+            println!("sample rate: {}", value_or_unset(rate));
+
+            let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+            if let Some(name) = profile::get_current_if_any(&profiles, rate, quantum) {
+                println!("profile: {}", name);
+            }
+        }
+        CliCommand::Set(values) => {
+            warn_if_unsupported(values.quantum, values.rate);
+            warn_about_fragile_clients(backend);
+            // limits first, so that the forced values are not clamped to the old ones
+            buffer_size::set_quantum_limits(backend, values.min_quantum, values.max_quantum)
+                .map_err(|e| format!("error setting quantum limits: {e}"))?;
+            if let Some(rates) = &values.allowed_rates {
+                sample_rate::set_allowed_sample_rates(backend, rates)
+                    .map_err(|e| format!("error setting allowed rates: {e}"))?;
+            }
+            // set the rate first, the same order the GUI applies profiles in
+            if let Some(rate) = values.rate {
+                sample_rate::set_sample_rate(backend, rate)
+                    .map_err(|e| format!("error setting sample rate: {e}"))?;
+            }
+            if let Some(quantum) = values.quantum {
+                buffer_size::set_buffer_size(backend, quantum)
+                    .map_err(|e| format!("error setting buffer size: {e}"))?;
+            }
+        }
+        CliCommand::Reset => {
+            execute(CliCommand::Set(ClockValues {
+                quantum: Some(0),
+                rate: Some(0),
+                ..Default::default()
+            }), backend)?;
+        }
+        CliCommand::ProfileList => {
+            for p in profile::load_profiles().map_err(|e| e.to_string())? {
+                print!("{}\t{}\t{} Hz", p.name, p.buffer_size, p.sample_rate);
+                if let (Some(min), Some(max)) = (p.min_quantum, p.max_quantum) {
+                    print!("\tquantum {}-{}", min, max);
+                }
+                if let Some(rates) = &p.allowed_rates {
+                    print!("\tallowed {}", sample_rate::format_rate_list(rates));
+                }
+                println!();
+            }
+        }
+        CliCommand::ProfileApply(name) => {
+            let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+            let chosen = profile::choose_profile(&profiles, &name)
+                .ok_or(format!("no profile named '{}'", name))?;
+            execute(CliCommand::Set(ClockValues {
+                quantum: Some(chosen.buffer_size),
+                rate: Some(chosen.sample_rate),
+                min_quantum: chosen.min_quantum,
+                max_quantum: chosen.max_quantum,
+                allowed_rates: chosen.allowed_rates,
+            }), backend)?;
+        }
+        CliCommand::ProfileSave { name, values } => {
+            let new_profile = LateProfile {
+                name,
+                buffer_size: match values.quantum {
+                    Some(quantum) => quantum,
+                    None => buffer_size::get_current_buffer_size(backend)
+                        .map_err(|e| format!("error reading buffer size: {e}"))?
+                        .unwrap_or(0),
+                },
+                sample_rate: match values.rate {
+                    Some(rate) => rate,
+                    None => sample_rate::get_current_sample_rate(backend)
+                        .map_err(|e| format!("error reading sample rate: {e}"))?
+                        .unwrap_or(0),
+                },
+                min_quantum: values.min_quantum,
+                max_quantum: values.max_quantum,
+                allowed_rates: values.allowed_rates,
+                ..Default::default()
+            };
+            // saving under an existing name replaces that profile
+            profile::update_profiles(|profiles| Ok(profile::upsert_profile(profiles, new_profile)?))
+                .map_err(|e| e.to_string())?;
+        }
+        CliCommand::ProfileDelete(name) => {
+            profile::update_profiles(|profiles| Ok(profiles.remove(profile::position(profiles, &name)?)))
+                .map_err(|e| e.to_string())?;
+        }
+        CliCommand::ProfileExport { file, names } => {
+            let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+            let chosen = if names.is_empty() {
+                profiles
+            } else {
+                names.iter()
+                    .map(|name| profile::choose_profile(&profiles, name)
+                        .ok_or(format!("no profile named '{}'", name)))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            profile_exchange::export(&chosen, file.as_ref())
+                .map_err(|e| format!("error writing {}: {e}", file))?;
+            println!("exported {} profiles to {}", chosen.len(), file);
+        }
+        CliCommand::ProfileImport { file, on_conflict } => {
+            let imported = profile_exchange::read(file.as_ref())
+                .map_err(|e| format!("error reading {}: {e}", file))?;
+            let (_, summary) = profile::update_profiles(|profiles| Ok(profile_exchange::merge(profiles, imported, on_conflict)))
+                .map_err(|e| e.to_string())?;
+            println!("{}", summary);
+        }
+        CliCommand::Persist { profile: name, confirmed } => {
+            let plan = match name {
+                Some(name) => {
+                    let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+                    let chosen = profile::choose_profile(&profiles, &name)
+                        .ok_or(format!("no profile named '{}'", name))?;
+                    persist::plan_write(&chosen)
+                }
+                None => persist::plan_remove(),
+            }.map_err(|e| e.to_string())?;
+
+            println!("{}:", plan.path.display());
+            for line in plan.diff() {
+                println!("{}", line);
+            }
+            for conflict in &plan.conflicts {
+                println!("note: {} is also set in {}{}", conflict.key, conflict.path.display(),
+                    if conflict.overrides_late { ", which takes precedence" } else { "" });
+            }
+
+            if !confirmed {
+                println!("nothing written, run again with --yes to apply");
+                return Ok(());
+            }
+            plan.apply()
+                .map_err(|e| format!("error writing {}: {e}", plan.path.display()))?;
+            println!("the changes take effect after pipewire restarts");
+        }
+        CliCommand::Serve => {
+            // always talks to pipewire, the service outlives any single call
+            dbus_service::run(|problem| {
+                eprintln!("{}", problem);
+                true
+            }).map_err(|e| format!("error providing {}: {e}", dbus_service::SERVICE_NAME))?;
+        }
+        CliCommand::Autostart { kind: None, .. } => {
+            for path in autostart::remove().map_err(|e| format!("error removing autostart: {e}"))? {
+                println!("removed {}", path.display());
+            }
+        }
+        CliCommand::Autostart { kind: Some(kind), profile: name } => {
+            let mut config = config::load_config().map_err(|e| e.to_string())?;
+            let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+            if let Some(name) = name.as_ref().filter(|name| profile::choose_profile(&profiles, name).is_none()) {
+                return Err(format!("no profile named '{}'", name));
+            }
+            config.startup_profile = name;
+            config::save_config(&config).map_err(|e| e.to_string())?;
+            // only one of them, so the profile is not applied twice
+            autostart::remove().map_err(|e| format!("error removing autostart: {e}"))?;
+            let path = autostart::install(kind).map_err(|e| format!("error writing autostart: {e}"))?;
+            println!("wrote {}", path.display());
+            if autostart::startup_profile(&config, &profiles).is_none() {
+                println!("note: there is no default profile yet, nothing will be applied");
+            }
+        }
+        CliCommand::ApplyStartup => {
+            let config = config::load_config().map_err(|e| e.to_string())?;
+            let profiles = profile::load_profiles().map_err(|e| e.to_string())?;
+            let Some(chosen) = autostart::startup_profile(&config, &profiles) else {
+                println!("no startup profile chosen and no default profile, nothing to apply");
+                return Ok(());
+            };
+            autostart::wait_for_pipewire(backend, autostart::PIPEWIRE_ATTEMPTS, autostart::PIPEWIRE_INTERVAL)
+                .map_err(|e| format!("pipewire did not come up: {e}"))?;
+            execute(CliCommand::ProfileApply(chosen.name), backend)?;
+        }
+    }
+    Ok(())
+}
+
+/// warns if the hardware or the pipewire limits don't allow the values.
+/// the values are still set, pipewire will clamp or ignore them
+fn warn_if_unsupported(quantum: Option<u32>, rate: Option<u32>) {
+    let Ok(limits) = hardware::query() else {
+        return;
+    };
+    if let Some((quantum, Err(e))) = quantum.map(|q| (q, limits.check_quantum(q))) {
+        eprintln!("warning: buffer size {}: {}", quantum, e);
+    }
+    if let Some((rate, Err(e))) = rate.map(|r| (r, limits.check_rate(r))) {
+        eprintln!("warning: sample rate {} Hz: {}", rate, e);
+    }
+}
+
+/// warns about running clients which are known to crash on changes.
+/// scripts can't answer a confirmation, so the values are still set
+fn warn_about_fragile_clients(backend: &dyn SettingsBackend) {
+    // a broken config still warns about the usual suspects
+    let denylist = config::load_config().unwrap_or_default().fragile_clients;
+    let clients = backend.active_clients().unwrap_or_else(|e| {
+        eprintln!("warning: could not list the running clients: {e}");
+        Vec::new()
+    });
+    for client in clients {
+        if client.is_fragile(&denylist) {
+            eprintln!("warning: {} is running and may crash", client);
+        }
+    }
+}
+
+fn value_or_unset(value: Option<u32>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "not set".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<CliCommand, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn parses_set() {
+        assert_eq!(parse("set --quantum 128 --rate 48000"),
+            Ok(CliCommand::Set(ClockValues { quantum: Some(128), rate: Some(48000), ..Default::default() })));
+        assert_eq!(parse("set -r 44100"),
+            Ok(CliCommand::Set(ClockValues { rate: Some(44100), ..Default::default() })));
+        assert_eq!(parse("set --min-quantum 64 --allowed-rates 44100,48000"),
+            Ok(CliCommand::Set(ClockValues {
+                min_quantum: Some(64),
+                allowed_rates: Some(vec![44100, 48000]),
+                ..Default::default()
+            })));
+        assert!(parse("set").is_err());
+        assert!(parse("set --quantum").is_err());
+        assert!(parse("set --quantum many").is_err());
+    }
+
+    #[test]
+    fn parses_profile_commands() {
+        assert_eq!(parse("profile list"), Ok(CliCommand::ProfileList));
+        assert_eq!(parse("profile apply Recording"),
+            Ok(CliCommand::ProfileApply("Recording".to_string())));
+        assert_eq!(parse("profile save Mixing --quantum 1024"),
+            Ok(CliCommand::ProfileSave {
+                name: "Mixing".to_string(),
+                values: ClockValues { quantum: Some(1024), ..Default::default() },
+            }));
+        assert_eq!(parse("profile delete Mixing"),
+            Ok(CliCommand::ProfileDelete("Mixing".to_string())));
+        assert!(parse("profile apply").is_err());
+        assert_eq!(parse("profile export team.toml Recording"),
+            Ok(CliCommand::ProfileExport { file: "team.toml".to_string(), names: vec!["Recording".to_string()] }));
+        assert_eq!(parse("profile import team.toml --on-conflict skip"),
+            Ok(CliCommand::ProfileImport { file: "team.toml".to_string(), on_conflict: OnConflict::Skip }));
+        assert!(parse("profile import team.toml --on-conflict merge").is_err());
+    }
+
+    #[test]
+    fn parses_persist() {
+        assert_eq!(parse("persist Recording"),
+            Ok(CliCommand::Persist { profile: Some("Recording".to_string()), confirmed: false }));
+        assert_eq!(parse("persist --remove --yes"),
+            Ok(CliCommand::Persist { profile: None, confirmed: true }));
+        assert!(parse("persist --yes").is_err());
+    }
+
+    #[test]
+    fn parses_autostart() {
+        assert_eq!(parse("autostart"), Ok(CliCommand::Autostart { kind: Some(AutostartKind::Desktop), profile: None }));
+        assert_eq!(parse("autostart --systemd Recording"), Ok(CliCommand::Autostart {
+            kind: Some(AutostartKind::Systemd),
+            profile: Some("Recording".to_string()),
+        }));
+        assert_eq!(parse("autostart --remove"), Ok(CliCommand::Autostart { kind: None, profile: None }));
+        assert!(parse("autostart --yes").is_err());
+        assert_eq!(parse("--apply-startup"), Ok(CliCommand::ApplyStartup));
+    }
+
+    #[test]
+    fn takes_config_dir() {
+        let mut args: Vec<String> = ["--config-dir", "/srv/late", "get"].map(String::from).to_vec();
+        assert_eq!(take_config_dir(&mut args), Some(PathBuf::from("/srv/late")));
+        assert_eq!(args, vec!["get"]);
+        let mut args = vec!["get".to_string()];
+        assert_eq!(take_config_dir(&mut args), None);
+        let mut args: Vec<String> = ["--config-dir", "late", "get"].map(String::from).to_vec();
+        assert_eq!(take_config_dir(&mut args), Some(std::env::current_dir().unwrap().join("late")));
+    }
+}
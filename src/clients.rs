@@ -0,0 +1,106 @@
+
+// the audio clients running in the pipewire graph.
+// changing buffer size or sample rate can crash some of them
+// (e.g. plugins running through wine), so late lists them before changing anything.
+
+use serde_json::Value;
+
+use crate::error::Result;
+use crate::pw_dump;
+
+/// A stream node of an application using pipewire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioClient {
+    /// application.name, or node.name if the application has no name
+    pub name: String,
+    /// application.process.binary
+    pub binary: Option<String>,
+    /// application.process.id
+    pub pid: Option<u32>,
+    /// e.g. "Stream/Output/Audio"
+    pub media_class: String,
+}
+
+impl AudioClient {
+    /// @returns whether name or binary contain any entry of the denylist (case insensitive)
+    pub fn is_fragile(&self, denylist: &[String]) -> bool {
+        let name = self.name.to_lowercase();
+        let binary = self.binary.as_deref().unwrap_or_default().to_lowercase();
+        denylist.iter()
+            .map(|entry| entry.trim().to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| name.contains(&entry) || binary.contains(&entry))
+    }
+}
+
+impl std::fmt::Display for AudioClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(binary) = &self.binary {
+            write!(f, " ({}", binary)?;
+            if let Some(pid) = self.pid {
+                write!(f, ", PID {}", pid)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, " - {}", self.media_class)
+    }
+}
+
+/// runs pw-dump and lists the active audio clients
+pub fn query() -> Result<Vec<AudioClient>> {
+    Ok(clients_from_dump(&pw_dump::dump()?))
+}
+
+/// @returns all audio streams which are running or idle (i.e. paused, but connected)
+pub fn clients_from_dump(objects: &[Value]) -> Vec<AudioClient> {
+    pw_dump::nodes(objects)
+        .filter(|n| matches!(n.state(), Some("running") | Some("idle")))
+        .filter_map(|n| {
+            let media_class = n.prop_str("media.class")?;
+            if !media_class.starts_with("Stream/") || !media_class.ends_with("/Audio") {
+                return None;
+            }
+            Some(AudioClient {
+                name: n.prop_str("application.name")
+                    .or(n.prop_str("node.name"))
+                    .unwrap_or("unknown")
+                    .to_string(),
+                binary: n.prop_str("application.process.binary").map(str::to_string),
+                pid: n.prop_u32("application.process.id"),
+                media_class: media_class.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SCARLETT: &str = include_str!("../tests/fixtures/pw-dump/scarlett.json");
+
+    #[test]
+    fn lists_streams_only() {
+        let clients = clients_from_dump(&pw_dump::parse(SCARLETT).unwrap());
+        assert_eq!(clients, vec![AudioClient {
+            name: "Firefox".to_string(),
+            binary: Some("firefox".to_string()),
+            pid: Some(4242),
+            media_class: "Stream/Output/Audio".to_string(),
+        }]);
+        assert_eq!(clients[0].to_string(), "Firefox (firefox, PID 4242) - Stream/Output/Audio");
+    }
+
+    #[test]
+    fn matches_denylist() {
+        let client = AudioClient {
+            name: "Amped Roots".to_string(),
+            binary: Some("wine64-preloader".to_string()),
+            pid: None,
+            media_class: "Stream/Output/Audio".to_string(),
+        };
+        assert!(client.is_fragile(&["Wine".to_string()]));
+        assert!(!client.is_fragile(&["reaper".to_string(), " ".to_string()]));
+    }
+}
@@ -1,87 +1,179 @@
 
 use iced::Theme;
-use std::path::PathBuf;
-use std::fs::{self, File};
-use std::io::Write;
 use serde::{Serialize, Deserialize};
-use crate::serde_helper::ThemeDef;
+use crate::serde_helper::{CustomTheme, ThemeDef};
+use crate::app_rules::AppRule;
+use crate::buffer_size;
+use crate::sample_rate;
+use crate::error::{LateError, Result};
 
-use crate::paths::CONFIG_PATH;
-use crate::paths::CONFIG_NAME;
+use std::path::Path;
 
-#[derive(Default, Serialize, Deserialize)]
+use crate::storage::{self, CONFIG_NAME};
+
+#[derive(Serialize, Deserialize)]
 pub struct LateConfig {
     #[serde(with = "ThemeDef")]
     pub theme: Theme,
+    /// clients known to crash when buffer size or sample rate change.
+    /// matched case insensitively against application name and binary
+    #[serde(default = "default_fragile_clients")]
+    pub fragile_clients: Vec<String>,
+    /// seconds until a change of buffer size or sample rate is reverted,
+    /// unless the user keeps it. 0 disables reverting
+    #[serde(default = "default_revert_timeout_secs")]
+    pub revert_timeout_secs: u32,
+    /// seconds the buffer size wizard tries each buffer size
+    #[serde(default = "default_wizard_soak_secs")]
+    pub wizard_soak_secs: u32,
+    /// profiles applied while certain applications run
+    #[serde(default)]
+    pub app_rules: Vec<AppRule>,
+    /// show a tray icon and hide the window instead of closing it
+    #[serde(default)]
+    pub minimize_to_tray: bool,
+    /// the profile `late --apply-startup` applies. None applies the default profile
+    #[serde(default)]
+    pub startup_profile: Option<String>,
+    /// the theme made with the palette editor, kept while another theme is chosen
+    #[serde(default)]
+    pub custom_theme: Option<CustomTheme>,
+    /// switch between light_theme and dark_theme as the desktop prefers, instead of using theme
+    #[serde(default)]
+    pub follow_system_theme: bool,
+    #[serde(with = "ThemeDef", default = "default_light_theme")]
+    pub light_theme: Theme,
+    #[serde(with = "ThemeDef", default = "default_dark_theme")]
+    pub dark_theme: Theme,
+    /// the buffer sizes offered to choose from, as far as pipewire allows them
+    #[serde(default = "default_favourite_buffer_sizes")]
+    pub favourite_buffer_sizes: Vec<u32>,
+    /// the sample rates offered to choose from, as far as the active devices support them
+    #[serde(default = "default_favourite_sample_rates")]
+    pub favourite_sample_rates: Vec<u32>,
 }
 
-// TODO: pretty much the same function as ensure_profiles_file, 
-// combine the shared code
-pub fn ensure_config_file() -> std::io::Result<PathBuf> {
-    let home_opt = home::home_dir();
-    if home_opt.is_some() {
-        let mut config = home_opt.unwrap();
-        config.push(CONFIG_PATH);
-
-        // ensure we can fetch the config dir and exists state
-        let config_dir_exists = fs::exists(&config);
-        if config_dir_exists.is_err() {
-            return Err(config_dir_exists.err().unwrap());
+impl Default for LateConfig {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            fragile_clients: default_fragile_clients(),
+            revert_timeout_secs: default_revert_timeout_secs(),
+            wizard_soak_secs: default_wizard_soak_secs(),
+            app_rules: Vec::new(),
+            minimize_to_tray: false,
+            startup_profile: None,
+            custom_theme: None,
+            follow_system_theme: false,
+            light_theme: default_light_theme(),
+            dark_theme: default_dark_theme(),
+            favourite_buffer_sizes: default_favourite_buffer_sizes(),
+            favourite_sample_rates: default_favourite_sample_rates(),
         }
+    }
+}
 
-        // ensure we have a config dir 
-        if !fs::exists(&config).unwrap() {
-            let result = fs::create_dir(&config);
-            if result.is_err() {
-                return Err(result.err().unwrap());
-            }
-        }
+fn default_light_theme() -> Theme {
+    Theme::Light
+}
 
-        // ensure we can fetch the config file and its exists state
-        config.push(CONFIG_NAME);
+fn default_dark_theme() -> Theme {
+    Theme::Dark
+}
 
-        let config_file_exists = fs::exists(&config);
-        if config_file_exists.is_err() {
-            return Err(config_file_exists.err().unwrap());
-        }
+/// the presets, without the reset
+fn default_favourite_buffer_sizes() -> Vec<u32> {
+    buffer_size::get_available_buffer_sizes().into_iter().filter(|s| *s != 0).collect()
+}
 
-        // ensure we have a config file
-        if !fs::exists(&config).unwrap() {
-            let result = File::create(&config);
-            if result.is_err() {
-                return Err(result.err().unwrap());
-            }
-        }
+fn default_favourite_sample_rates() -> Vec<u32> {
+    sample_rate::get_available_sample_rates().into_iter().filter(|r| *r != 0).collect()
+}
 
-        return Ok(config);
-    }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "Cannot find home directory!"))
+fn default_revert_timeout_secs() -> u32 {
+    15
+}
+
+fn default_wizard_soak_secs() -> u32 {
+    30
+}
+
+fn default_fragile_clients() -> Vec<String> {
+    // plugins and programs running through wine are the usual suspects
+    vec![
+        "wine".to_string(),
+        "yabridge".to_string(),
+        ".exe".to_string(),
+    ]
 }
 
-pub fn save_config(config: &LateConfig) {
-    let serialized = serde_json::to_string(&config);
-    let config_file = match ensure_config_file(){
-        Ok(c) => c,
-        Err(e) => { 
-            print!("{}", e);
-            return;
+pub fn save_config(config: &LateConfig) -> Result<()> {
+    save_config_in(&storage::config_dir()?, config)
+}
+
+/// saves the config in dir instead of the config dir.
+/// a config which can't be read is only replaced once there is a copy of it, as load_config says
+pub fn save_config_in(dir: &Path, config: &LateConfig) -> Result<()> {
+    let serialized = serde_json::to_string(&config)
+        .expect("the config only holds values JSON can represent");
+    storage::update_in(dir, CONFIG_NAME, |contents| {
+        if parse(contents).is_err() {
+            // backup finds the copy made while loading
+            let path = dir.join(CONFIG_NAME);
+            storage::backup(&path, "broken").map_err(|e| LateError::io(&path, e))?;
         }
-    };
+        Ok(serialized)
+    })
+}
 
-    let f = File::create(config_file);
-    write!(f.unwrap(), "{}", serialized.unwrap())
-        .expect("Could not write config to file!");
+/// reads the config. without a config file, the defaults are used.
+/// a file which can't be read is backed up, since the next save overwrites it
+pub fn load_config() -> Result<LateConfig> {
+    load_config_in(&storage::config_dir()?)
+}
 
+fn load_config_in(dir: &Path) -> Result<LateConfig> {
+    let path = dir.join(CONFIG_NAME);
+    parse(&storage::read_in(dir, CONFIG_NAME)?).map_err(|e| {
+        let copy = match storage::backup(&path, "broken") {
+            Ok(backup) => format!("saved a copy as {}", backup.display()),
+            Err(e) => format!("could not back it up: {}", e),
+        };
+        LateError::Parse { what: path.display().to_string(), message: format!("{} ({})", e, copy) }
+    })
 }
 
-pub fn load_config() -> LateConfig {
-    let config_path = ensure_config_file().unwrap_or_default();
-    let file_contents = fs::read_to_string(config_path);
-    match file_contents {
-        Ok(s) => serde_json::from_str(&s).unwrap_or_default(),
-        Err(_) => LateConfig { theme: Theme::Dark, }
+fn parse(file_contents: &str) -> serde_json::Result<LateConfig> {
+    if file_contents.trim().is_empty() {
+        return Ok(LateConfig::default());
     }
+    serde_json::from_str(file_contents)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn broken_config_is_backed_up_before_saving() {
+        let dir = std::env::temp_dir().join(format!("late-config-{}", std::process::id()));
+        let broken = "{\"theme\": ";
+        storage::write_in(&dir, CONFIG_NAME, broken).unwrap();
+
+        let error = load_config_in(&dir).err().unwrap().to_string();
+        assert!(error.contains("saved a copy as"), "{error}");
+        save_config_in(&dir, &LateConfig::default()).unwrap();
+        assert!(load_config_in(&dir).is_ok());
+        let copies: Vec<String> = fs::read_dir(&dir).unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|e| e == "bak"))
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        // saving found the copy made while loading
+        assert_eq!(copies, vec![broken]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
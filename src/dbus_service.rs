@@ -0,0 +1,288 @@
+// the org.late.Settings D-Bus interface, for scripts and desktop widgets.
+// runs with `late serve` or as part of `late --daemon`, e.g.
+/*
+* busctl --user call org.late.Settings /org/late/Settings org.late.Settings SetQuantum u 256
+* busctl --user get-property org.late.Settings /org/late/Settings org.late.Settings Quantum
+*/
+// the values are read from and written to pipewire on every call. PropertiesChanged
+// is emitted for every change of the forced values, whoever made it.
+// the service keeps running if pw-metadata exits, it is started again by watch_settings.
+
+use std::thread;
+
+use iced::futures::channel::mpsc;
+use iced::futures::{executor, SinkExt, Stream, StreamExt};
+use zbus::blocking::{connection, Connection};
+use zbus::fdo;
+
+use crate::buffer_size;
+use crate::error::{self, LateError};
+use crate::profile::{self, LateProfile};
+use crate::pw_metadata::MetadataLine;
+use crate::sample_rate;
+use crate::settings_backend::{self, SettingsBackend, PwMetadataBackend, FORCE_QUANTUM_KEY, FORCE_RATE_KEY};
+
+pub static SERVICE_NAME: &str = "org.late.Settings";
+pub static OBJECT_PATH: &str = "/org/late/Settings";
+
+pub struct SettingsService {
+    backend: Box<dyn SettingsBackend + Send + Sync>,
+}
+
+fn failed(e: LateError) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+#[zbus::interface(name = "org.late.Settings")]
+impl SettingsService {
+    /// @returns the forced buffer size, 0 if none is forced
+    fn get_quantum(&self) -> fdo::Result<u32> {
+        buffer_size::get_current_buffer_size(self.backend.as_ref()).map(|q| q.unwrap_or(0)).map_err(failed)
+    }
+
+    /// forces the buffer size, 0 resets it
+    fn set_quantum(&self, quantum: u32) -> fdo::Result<()> {
+        buffer_size::set_buffer_size(self.backend.as_ref(), quantum).map_err(failed)
+    }
+
+    /// @returns the forced sample rate, 0 if none is forced
+    fn get_rate(&self) -> fdo::Result<u32> {
+        sample_rate::get_current_sample_rate(self.backend.as_ref()).map(|r| r.unwrap_or(0)).map_err(failed)
+    }
+
+    /// forces the sample rate, 0 resets it
+    fn set_rate(&self, rate: u32) -> fdo::Result<()> {
+        sample_rate::set_sample_rate(self.backend.as_ref(), rate).map_err(failed)
+    }
+
+    fn list_profiles(&self) -> fdo::Result<Vec<String>> {
+        Ok(profile::get_profile_names(&profile::load_profiles().map_err(failed)?))
+    }
+
+    fn apply_profile(&self, name: String) -> fdo::Result<()> {
+        let chosen = profile::choose_profile(&profile::load_profiles().map_err(failed)?, &name)
+            .ok_or(fdo::Error::InvalidArgs(format!("no profile named '{}'", name)))?;
+        let backend = self.backend.as_ref();
+        // limits first, so that the forced values are not clamped to the old ones
+        buffer_size::set_quantum_limits(backend, chosen.min_quantum, chosen.max_quantum).map_err(failed)?;
+        if let Some(rates) = &chosen.allowed_rates {
+            sample_rate::set_allowed_sample_rates(backend, rates).map_err(failed)?;
+        }
+        sample_rate::set_sample_rate(backend, chosen.sample_rate).map_err(failed)?;
+        buffer_size::set_buffer_size(backend, chosen.buffer_size).map_err(failed)
+    }
+
+    /// saves the forced values as profile. an existing profile with that name is replaced
+    fn save_profile(&self, name: String) -> fdo::Result<()> {
+        let new_profile = LateProfile {
+            name,
+            buffer_size: self.get_quantum()?,
+            sample_rate: self.get_rate()?,
+            ..Default::default()
+        };
+        match profile::update_profiles(|profiles| Ok(profile::upsert_profile(profiles, new_profile)?)) {
+            Ok(_) => Ok(()),
+            Err(LateError::Name(e)) => Err(fdo::Error::InvalidArgs(e.to_string())),
+            Err(e) => Err(failed(e)),
+        }
+    }
+
+    /// the forced buffer size, 0 if none is forced
+    #[zbus(property)]
+    fn quantum(&self) -> fdo::Result<u32> {
+        self.get_quantum()
+    }
+
+    /// the forced sample rate, 0 if none is forced
+    #[zbus(property)]
+    fn rate(&self) -> fdo::Result<u32> {
+        self.get_rate()
+    }
+}
+
+/// puts the service on the bus builder connects to
+/// @returns the connection, the service is available as long as it is kept
+pub fn serve(builder: connection::Builder<'_>, backend: Box<dyn SettingsBackend + Send + Sync>)
+    -> zbus::Result<Connection> {
+    builder
+        .name(SERVICE_NAME)?
+        .serve_at(OBJECT_PATH, SettingsService { backend })?
+        .build()
+}
+
+/// runs the service on the session bus, until report returns false.
+/// problems which don't stop the service (e.g. pw-metadata exiting) are passed to report
+/// @returns an error if the service can't be put on the bus
+pub fn run(report: impl Fn(String) -> bool) -> zbus::Result<()> {
+    let connection = serve(connection::Builder::session()?, Box::new(PwMetadataBackend))?;
+    let lines = executor::block_on_stream(Box::pin(settings_backend::watch_settings()));
+    announce_changes(&connection, lines, report)
+}
+
+/// runs the service on the session bus as long as the stream is alive.
+/// @returns a stream of the problems running it
+pub fn watch() -> impl Stream<Item = String> {
+    iced::stream::channel(10, |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            if let Err(e) = run(|problem| sender.unbounded_send(problem).is_ok()) {
+                let _ = sender.unbounded_send(format!("Could not provide {SERVICE_NAME}: {e}"));
+            }
+        });
+
+        while let Some(problem) = receiver.next().await {
+            if output.send(problem).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// emits PropertiesChanged for every change of the forced values in lines, until report returns false
+fn announce_changes(connection: &Connection, lines: impl Iterator<Item = error::Result<MetadataLine>>,
+    report: impl Fn(String) -> bool) -> zbus::Result<()> {
+    let service = connection.object_server().interface::<_, SettingsService>(OBJECT_PATH)?;
+
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            // the values are read on every call, only the signals are missed meanwhile
+            Err(e) if report(format!("Could not watch the settings: {e}")) => continue,
+            Err(_) => break,
+        };
+        let (quantum_changed, rate_changed) = match &line {
+            MetadataLine::Update(entry) if entry.id == 0 =>
+                (entry.key == FORCE_QUANTUM_KEY, entry.key == FORCE_RATE_KEY),
+            MetadataLine::Remove { id: 0, key: Some(key) } =>
+                (key == FORCE_QUANTUM_KEY, key == FORCE_RATE_KEY),
+            MetadataLine::Remove { id: 0, key: None } => (true, true),
+            _ => (false, false),
+        };
+        let mut sent = Ok(());
+        if quantum_changed {
+            sent = executor::block_on(service.get().quantum_changed(service.signal_context()));
+        }
+        if rate_changed {
+            sent = sent.and(executor::block_on(service.get().rate_changed(service.signal_context())));
+        }
+        if let Err(e) = sent {
+            if !report(format!("Could not announce the changed settings: {e}")) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use crate::pw_metadata::MetadataEntry;
+    use crate::settings_backend::MemoryBackend;
+
+    /// a dbus-daemon only for this test, killed when dropped
+    pub(crate) struct PrivateBus {
+        daemon: Child,
+        pub address: String,
+    }
+
+    impl PrivateBus {
+        /// @returns None if dbus-daemon is not installed
+        pub fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?).read_line(&mut address).ok()?;
+            Some(Self { daemon, address: address.trim().to_string() })
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[test]
+    fn sets_and_gets_values_over_the_bus() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("dbus-daemon not available, skipping");
+            return;
+        };
+        let backend = MemoryBackend::default();
+        let _service = serve(connection::Builder::address(bus.address.as_str()).unwrap(),
+            Box::new(backend.clone())).unwrap();
+        let client = connection::Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let call = |method: &str, body: &(u32,)| client
+            .call_method(Some(SERVICE_NAME), OBJECT_PATH, Some(SERVICE_NAME), method, body);
+
+        call("SetQuantum", &(128,)).unwrap();
+        call("SetRate", &(44100,)).unwrap();
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("128"));
+
+        let quantum: u32 = client
+            .call_method(Some(SERVICE_NAME), OBJECT_PATH, Some(SERVICE_NAME), "GetQuantum", &())
+            .unwrap().body().deserialize().unwrap();
+        assert_eq!(quantum, 128);
+        let rate: zbus::zvariant::OwnedValue = client
+            .call_method(Some(SERVICE_NAME), OBJECT_PATH, Some("org.freedesktop.DBus.Properties"),
+                "Get", &(SERVICE_NAME, "Rate"))
+            .unwrap().body().deserialize().unwrap();
+        assert_eq!(rate, zbus::zvariant::OwnedValue::from(44100u32));
+
+        call("SetQuantum", &(0,)).unwrap();
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+    }
+
+    #[test]
+    fn announces_changed_values() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("dbus-daemon not available, skipping");
+            return;
+        };
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "128").unwrap();
+        let service = serve(connection::Builder::address(bus.address.as_str()).unwrap(),
+            Box::new(backend.clone())).unwrap();
+        let client = connection::Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let properties = zbus::blocking::fdo::PropertiesProxy::builder(&client)
+            .destination(SERVICE_NAME).unwrap()
+            .path(OBJECT_PATH).unwrap()
+            .build().unwrap();
+        let mut changes = properties.receive_properties_changed().unwrap();
+
+        let update = |key: &str| Ok(MetadataLine::Update(MetadataEntry {
+            id: 0,
+            key: key.to_string(),
+            value: "128".to_string(),
+            value_type: String::new(),
+        }));
+        let lines = vec![
+            update("clock.quantum"),
+            Err(LateError::Exit { program: "pw-metadata".to_string(), code: None, stderr: String::new() }),
+            update(FORCE_QUANTUM_KEY),
+        ];
+        let problems = std::sync::Mutex::new(Vec::new());
+        announce_changes(&service, lines.into_iter(), |problem| {
+            problems.lock().unwrap().push(problem);
+            true
+        }).unwrap();
+        assert_eq!(problems.into_inner().unwrap(), vec!["Could not watch the settings: pw-metadata was killed"]);
+
+        // only the forced quantum is announced
+        let signal = changes.next().unwrap();
+        let args = signal.args().unwrap();
+        assert_eq!(args.interface_name().as_str(), SERVICE_NAME);
+        let changed: Vec<(&str, u32)> = args.changed_properties().iter()
+            .map(|(name, value)| (*name, u32::try_from(value).unwrap()))
+            .collect();
+        assert_eq!(changed, vec![("Quantum", 128)]);
+    }
+}
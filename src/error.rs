@@ -0,0 +1,98 @@
+// the errors late reports to the user.
+// everything talking to pipewire or touching late's files returns LateError, so that the
+// GUI can show what went wrong instead of printing it to a terminal nobody looks at.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::hardware::LimitError;
+use crate::profile::NameError;
+
+/// Anything that can go wrong reading or changing settings, config and profiles
+#[derive(Debug)]
+pub enum LateError {
+    /// a program (e.g. pw-metadata) could not be started, most likely it isn't installed
+    Spawn { program: String, source: io::Error },
+    /// a program exited unsuccessfully. code is None if it was killed by a signal
+    Exit { program: String, code: Option<i32>, stderr: String },
+    /// reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+    /// what (a file or the output of a program) could not be parsed
+    Parse { what: String, message: String },
+    /// there is no home directory to keep config and profiles in
+    NoHome,
+    /// a profile name which can't be used, e.g. while changing the saved profiles
+    Name(NameError),
+    /// a value pipewire would not accept, e.g. a min quantum above the max quantum
+    Limit(LimitError),
+}
+
+impl LateError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        LateError::Io { path: path.into(), source }
+    }
+}
+
+impl From<NameError> for LateError {
+    fn from(error: NameError) -> Self {
+        LateError::Name(error)
+    }
+}
+
+impl From<LimitError> for LateError {
+    fn from(error: LimitError) -> Self {
+        LateError::Limit(error)
+    }
+}
+
+impl fmt::Display for LateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LateError::Spawn { program, source } => write!(f, "could not run {}: {}", program, source),
+            LateError::Exit { program, code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "{} exited with code {}", program, code)?,
+                    None => write!(f, "{} was killed", program)?,
+                }
+                if !stderr.trim().is_empty() {
+                    write!(f, ": {}", stderr.trim())?;
+                }
+                Ok(())
+            }
+            LateError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            LateError::Parse { what, message } => write!(f, "could not read {}: {}", what, message),
+            LateError::NoHome => write!(f, "cannot find home directory"),
+            LateError::Name(e) => write!(f, "{}", e),
+            LateError::Limit(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LateError::Spawn { source, .. } | LateError::Io { source, .. } => Some(source),
+            LateError::Name(e) => Some(e),
+            LateError::Limit(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, LateError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_exit_code_and_stderr() {
+        let error = LateError::Exit {
+            program: "pw-metadata".to_string(),
+            code: Some(255),
+            stderr: "can't connect: Host is down\n".to_string(),
+        };
+        assert_eq!(error.to_string(), "pw-metadata exited with code 255: can't connect: Host is down");
+    }
+}
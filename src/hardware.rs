@@ -0,0 +1,395 @@
+
+// what the hardware and the pipewire configuration actually allow.
+// read from pw-dump: the clock limits of the settings metadata and the
+// formats (EnumFormat) the default alsa devices report.
+
+use std::fmt;
+use std::time::Duration;
+use iced::futures::Stream;
+use serde_json::Value;
+
+use crate::clients::{self, AudioClient};
+use crate::error;
+use crate::process_stream;
+use crate::pw_dump::{self, Node};
+
+/// The sample rates a single device can run at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateSupport {
+    /// a fixed set of rates
+    List(Vec<u32>),
+    /// any rate in between min and max (inclusive)
+    Range { min: u32, max: u32 },
+}
+
+impl RateSupport {
+    pub fn supports(&self, rate: u32) -> bool {
+        match self {
+            RateSupport::List(rates) => rates.contains(&rate),
+            RateSupport::Range { min, max } => (*min..=*max).contains(&rate),
+        }
+    }
+}
+
+/// the defaults of clock.quantum-floor and clock.quantum-limit, for when they can't be read
+pub const QUANTUM_RANGE: (u32, u32) = (4, 8192);
+/// the sample rates pipewire can run at at all
+pub const RATE_RANGE: (u32, u32) = (8000, 768000);
+
+/// Why a buffer size or sample rate can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    NotANumber(String),
+    /// outside of what pipewire can run at at all
+    OutOfRange { min: u32, max: u32 },
+    /// outside of clock.min-quantum and clock.max-quantum
+    OutsideQuantumLimits { min: Option<u32>, max: Option<u32> },
+    /// an active device can't run at the rate
+    UnsupportedRate { device: String },
+    /// clock.min-quantum would be larger than clock.max-quantum
+    MinAboveMax { min: u32, max: u32 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::NotANumber(text) => write!(f, "'{text}' is not a number"),
+            LimitError::OutOfRange { min, max } => write!(f, "pipewire only supports {min} to {max}"),
+            LimitError::OutsideQuantumLimits { min, max } => {
+                let limit = |limit: &Option<u32>| limit.map_or("not set".to_string(), |l| l.to_string());
+                write!(f, "outside of the quantum limits (min {}, max {})", limit(min), limit(max))
+            }
+            LimitError::UnsupportedRate { device } => write!(f, "{device} can't run at this rate"),
+            LimitError::MinAboveMax { min, max } => {
+                write!(f, "the min quantum {min} is larger than the max quantum {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Limits for buffer size and sample rate. Empty values mean "unknown", i.e. no restriction
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HardwareLimits {
+    /// clock.allowed-rates of the settings metadata
+    pub allowed_rates: Vec<u32>,
+    /// clock.min-quantum of the settings metadata
+    pub min_quantum: Option<u32>,
+    /// clock.max-quantum of the settings metadata
+    pub max_quantum: Option<u32>,
+    /// clock.quantum-floor of the settings metadata, the smallest quantum pipewire runs at at all
+    pub quantum_floor: Option<u32>,
+    /// clock.quantum-limit of the settings metadata, the largest quantum pipewire runs at at all
+    pub quantum_limit: Option<u32>,
+    /// the rates supported by each of the active alsa devices, by node name
+    pub devices: Vec<(String, RateSupport)>,
+}
+
+impl HardwareLimits {
+    /// @returns whether all active devices can run at rate. 0 (the reset) is always supported
+    pub fn supports_rate(&self, rate: u32) -> bool {
+        self.check_rate(rate).is_ok()
+    }
+
+    /// @returns whether quantum is within the quantum limits. 0 (the reset) is always supported
+    pub fn supports_quantum(&self, quantum: u32) -> bool {
+        self.check_quantum(quantum).is_ok()
+    }
+
+    /// @returns why the active devices can't run at rate, if they can't
+    pub fn check_rate(&self, rate: u32) -> Result<(), LimitError> {
+        if rate == 0 {
+            return Ok(());
+        }
+        check_range(rate, RATE_RANGE)?;
+        match self.devices.iter().find(|(_, support)| !support.supports(rate)) {
+            Some((device, _)) => Err(LimitError::UnsupportedRate { device: device.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    /// @returns why pipewire won't run at quantum, if it won't
+    pub fn check_quantum(&self, quantum: u32) -> Result<(), LimitError> {
+        if quantum == 0 {
+            return Ok(());
+        }
+        check_range(quantum, self.quantum_range())?;
+        if self.min_quantum.is_some_and(|min| quantum < min) || self.max_quantum.is_some_and(|max| quantum > max) {
+            return Err(LimitError::OutsideQuantumLimits { min: self.min_quantum, max: self.max_quantum });
+        }
+        Ok(())
+    }
+
+    /// @returns clock.quantum-floor and clock.quantum-limit, their defaults if they are unknown
+    pub fn quantum_range(&self) -> (u32, u32) {
+        (self.quantum_floor.unwrap_or(QUANTUM_RANGE.0), self.quantum_limit.unwrap_or(QUANTUM_RANGE.1))
+    }
+}
+
+fn check_range(value: u32, (min, max): (u32, u32)) -> Result<(), LimitError> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(LimitError::OutOfRange { min, max })
+    }
+}
+
+/// parses a buffer size or sample rate as typed by the user
+pub fn parse_value(text: &str) -> Result<u32, LimitError> {
+    let text = text.trim();
+    text.parse().map_err(|_| LimitError::NotANumber(text.to_string()))
+}
+
+/// The clock of the driver node the graph currently runs on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriverClock {
+    /// node.name of the driver
+    pub name: String,
+    pub quantum: Option<u32>,
+    pub rate: Option<u32>,
+}
+
+/// What runs in the graph, read from a single pw-dump
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphState {
+    pub driver: Option<DriverClock>,
+    /// the audio clients a change of the clock might crash
+    pub clients: Vec<AudioClient>,
+}
+
+/// runs pw-dump and reads the limits from it
+pub fn query() -> error::Result<HardwareLimits> {
+    Ok(limits_from_dump(&pw_dump::dump()?))
+}
+
+pub fn limits_from_dump(objects: &[Value]) -> HardwareLimits {
+    let setting = |key| pw_dump::metadata_value(objects, "settings", key);
+
+    let allowed_rates = match setting("clock.allowed-rates") {
+        Some(Value::Array(rates)) => rates.iter().filter_map(pw_dump::value_as_u32).collect(),
+        // older versions report the list as the string "[ 44100 48000 ]"
+        Some(Value::String(s)) => parse_rate_list(s),
+        _ => Vec::new(),
+    };
+
+    HardwareLimits {
+        allowed_rates,
+        min_quantum: setting("clock.min-quantum").and_then(pw_dump::value_as_u32),
+        max_quantum: setting("clock.max-quantum").and_then(pw_dump::value_as_u32),
+        quantum_floor: setting("clock.quantum-floor").and_then(pw_dump::value_as_u32),
+        quantum_limit: setting("clock.quantum-limit").and_then(pw_dump::value_as_u32),
+        devices: active_devices(objects)
+            .into_iter()
+            .filter_map(|node| Some((
+                node.prop_str("node.name")?.to_string(),
+                rate_support(&node)?,
+            )))
+            .collect(),
+    }
+}
+
+/// runs pw-dump and reads the running driver and the active clients from it
+pub fn query_graph() -> error::Result<GraphState> {
+    let objects = pw_dump::dump()?;
+    Ok(GraphState {
+        driver: driver_from_dump(&objects),
+        clients: clients::clients_from_dump(&objects),
+    })
+}
+
+/// @returns a stream of the running driver and the active clients, or why they can't be read,
+/// polled every two seconds
+pub fn watch_graph() -> impl Stream<Item = Result<GraphState, String>> {
+    process_stream::poll(Duration::from_secs(2), || query_graph()
+        .map_err(|e| format!("Could not read the running driver and clients: {e}")))
+}
+
+/// @returns the running driver with the highest priority, i.e. the one pipewire picks
+pub fn driver_from_dump(objects: &[Value]) -> Option<DriverClock> {
+    let driver = pw_dump::nodes(objects)
+        .filter(|n| n.prop("node.driver") == true && n.state() == Some("running"))
+        .max_by_key(|n| n.prop_u32("priority.driver").unwrap_or(0))?;
+
+    // prefer clock.quantum / clock.rate, if the driver publishes what it runs at.
+    // otherwise fall back to the latency the driver asked for, e.g. "1024/48000"
+    let latency = driver.prop_str("node.latency")
+        .and_then(|l| l.split_once('/'))
+        .map(|(q, r)| (q.parse().ok(), r.parse().ok()));
+    let (latency_quantum, latency_rate) = latency.unwrap_or((None, None));
+
+    Some(DriverClock {
+        name: driver.prop_str("node.name").unwrap_or_default().to_string(),
+        quantum: driver.prop_u32("clock.quantum").or(latency_quantum),
+        rate: driver.prop_u32("clock.rate").or(latency_rate),
+    })
+}
+
+/// parses a pipewire rate list like "[ 44100 48000 ]" or "[ 44100, 48000 ]"
+pub fn parse_rate_list(s: &str) -> Vec<u32> {
+    s.trim_matches(|c: char| c == '[' || c == ']' || c.is_whitespace())
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|r| r.parse().ok())
+        .collect()
+}
+
+/// @returns the default sink and source if they are alsa devices,
+/// otherwise all alsa sinks and sources which are currently running
+pub fn active_devices(objects: &[Value]) -> Vec<Node<'_>> {
+    let default_name = |key| {
+        let value = pw_dump::metadata_value(objects, "default", key)?;
+        match value {
+            Value::Object(_) => value["name"].as_str().map(str::to_string),
+            // depending on the version, the JSON is still a string
+            Value::String(s) => serde_json::from_str::<Value>(s).ok()?["name"]
+                .as_str().map(str::to_string),
+            _ => None,
+        }
+    };
+    let defaults: Vec<String> = ["default.audio.sink", "default.audio.source"]
+        .into_iter()
+        .filter_map(default_name)
+        .collect();
+
+    let alsa_devices: Vec<Node> = pw_dump::nodes(objects)
+        .filter(|n| n.prop_str("device.api") == Some("alsa"))
+        .filter(|n| matches!(n.prop_str("media.class"),
+            Some("Audio/Sink") | Some("Audio/Source") | Some("Audio/Duplex")))
+        .collect();
+
+    let (default_devices, others): (Vec<Node>, Vec<Node>) = alsa_devices.into_iter()
+        .partition(|n| n.prop_str("node.name").is_some_and(|name| defaults.iter().any(|d| d == name)));
+    if !default_devices.is_empty() {
+        return default_devices;
+    }
+    others.into_iter()
+        .filter(|n| n.state() == Some("running"))
+        .collect()
+}
+
+/// reads the rate of the raw audio EnumFormat params of a node
+fn rate_support(node: &Node) -> Option<RateSupport> {
+    let mut rates = Vec::new();
+    for format in node.params("EnumFormat") {
+        if format["mediaSubtype"] != "raw" {
+            continue;
+        }
+        match &format["rate"] {
+            Value::Object(choice) => {
+                // a range looks like { "default": 48000, "min": 44100, "max": 192000 }
+                if let (Some(min), Some(max)) = (
+                    choice.get("min").and_then(pw_dump::value_as_u32),
+                    choice.get("max").and_then(pw_dump::value_as_u32)) {
+                    return Some(RateSupport::Range { min, max });
+                }
+                // an enum looks like { "default": 48000, "alt1": 44100, "alt2": 48000 }
+                rates.extend(choice.values().filter_map(pw_dump::value_as_u32));
+            }
+            rate => rates.extend(pw_dump::value_as_u32(rate)),
+        }
+    }
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_unstable();
+    rates.dedup();
+    Some(RateSupport::List(rates))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SCARLETT: &str = include_str!("../tests/fixtures/pw-dump/scarlett.json");
+    static DRIVERS: &str = include_str!("../tests/fixtures/pw-dump/drivers.json");
+
+    fn scarlett_limits() -> HardwareLimits {
+        limits_from_dump(&pw_dump::parse(SCARLETT).unwrap())
+    }
+
+    #[test]
+    fn reads_settings_limits() {
+        let limits = scarlett_limits();
+        assert_eq!(limits.allowed_rates, vec![44100, 48000, 96000]);
+        assert_eq!(limits.min_quantum, Some(64));
+        assert_eq!(limits.max_quantum, Some(1024));
+        assert!(limits.supports_quantum(0));
+        assert!(!limits.supports_quantum(32));
+        assert!(!limits.supports_quantum(2048));
+    }
+
+    #[test]
+    fn reads_quantum_floor_and_limit() {
+        assert_eq!(scarlett_limits().quantum_range(), QUANTUM_RANGE);
+
+        let limits = limits_from_dump(&pw_dump::parse(r#"[{ "type": "PipeWire:Interface:Metadata",
+            "props": { "metadata.name": "settings" },
+            "metadata": [ { "subject": 0, "key": "clock.quantum-floor", "type": "", "value": 16 },
+                          { "subject": 0, "key": "clock.quantum-limit", "type": "", "value": 4096 } ] }]"#).unwrap());
+        assert_eq!(limits.quantum_range(), (16, 4096));
+        assert_eq!(limits.check_quantum(8192), Err(LimitError::OutOfRange { min: 16, max: 4096 }));
+        assert!(limits.supports_quantum(16));
+        assert!(!limits.supports_quantum(8));
+    }
+
+    #[test]
+    fn reads_formats_of_default_devices_only() {
+        let limits = scarlett_limits();
+        // the suspended HDMI sink only supports 48000, but isn't the default device
+        assert_eq!(limits.devices, vec![
+            ("alsa_output.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo".to_string(),
+                RateSupport::List(vec![44100, 48000, 88200, 96000, 176400, 192000])),
+            ("alsa_input.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo".to_string(),
+                RateSupport::Range { min: 44100, max: 192000 }),
+        ]);
+        assert!(limits.supports_rate(44100));
+        assert!(!limits.supports_rate(22050));
+        assert!(!limits.supports_rate(24000));
+    }
+
+    #[test]
+    fn explains_unsupported_values() {
+        let limits = scarlett_limits();
+        assert_eq!(limits.check_rate(176400), Ok(()));
+        assert_eq!(limits.check_rate(32000), Err(LimitError::UnsupportedRate {
+            device: "alsa_output.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo".to_string(),
+        }));
+        assert_eq!(limits.check_quantum(2048).unwrap_err().to_string(),
+            "outside of the quantum limits (min 64, max 1024)");
+        assert_eq!(HardwareLimits::default().check_quantum(16384), Err(LimitError::OutOfRange { min: 4, max: 8192 }));
+        assert_eq!(parse_value(" 96 "), Ok(96));
+        assert!(parse_value("96k").is_err());
+    }
+
+    #[test]
+    fn finds_running_driver() {
+        // the dummy and freewheel drivers have the highest priority, but are suspended
+        let driver = driver_from_dump(&pw_dump::parse(DRIVERS).unwrap()).unwrap();
+        assert_eq!(driver, DriverClock {
+            name: "alsa_input.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo".to_string(),
+            quantum: Some(128),
+            rate: Some(48000),
+        });
+
+        // the source is idle, so the sink drives the graph. it doesn't say what it runs at
+        let driver = driver_from_dump(&pw_dump::parse(SCARLETT).unwrap()).unwrap();
+        assert_eq!(driver.name, "alsa_output.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo");
+        assert_eq!((driver.quantum, driver.rate), (None, None));
+    }
+
+    #[test]
+    fn driver_clock_wins_over_its_latency() {
+        let mut objects = pw_dump::parse(DRIVERS).unwrap();
+        // only the sink is left running
+        objects[3]["info"]["state"] = "idle".into();
+        let driver = driver_from_dump(&objects).unwrap();
+        assert_eq!((driver.quantum, driver.rate), (Some(256), Some(44100)));
+    }
+
+    #[test]
+    fn parses_rate_list_strings() {
+        assert_eq!(parse_rate_list("[ 44100 48000 ]"), vec![44100, 48000]);
+        assert_eq!(parse_rate_list("[44100, 48000]"), vec![44100, 48000]);
+        assert_eq!(parse_rate_list("[ ]"), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,179 @@
+// estimating the latency from the input to the output devices.
+// pipewire processes one quantum per graph cycle. an alsa device keeps quantum + api.alsa.headroom
+// frames buffered, at most as many as its periods (api.alsa.period-size × api.alsa.period-num) hold.
+// on top of that comes the latency the node reports itself (latency.internal.rate frames and
+// latency.internal.ns). playback (one way) is a graph cycle plus the output device, the round trip
+// adds the input device.
+// converters and the USB stack of the interface add more, which is why a measured round trip
+// (e.g. with jack_iodelay) can be entered to compare.
+
+use std::time::Duration;
+
+use iced::futures::Stream;
+use serde_json::Value;
+
+use crate::error;
+use crate::hardware;
+use crate::process_stream;
+use crate::pw_dump::{self, Node};
+
+/// Whether a device records or plays back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Capture,
+    Playback,
+}
+
+/// What an active alsa device adds to the latency
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceLatency {
+    /// node.description, or node.name if there is none
+    pub name: String,
+    pub direction: Direction,
+    /// api.alsa.headroom, in frames
+    pub headroom: u32,
+    pub period_size: Option<u32>,
+    pub period_num: Option<u32>,
+    /// latency.internal.rate, in frames
+    pub internal_frames: u32,
+    /// latency.internal.ns
+    pub internal_ns: u32,
+}
+
+impl DeviceLatency {
+    /// @returns the frames the device keeps buffered when the graph runs at quantum
+    pub fn buffered_frames(&self, quantum: u32) -> u32 {
+        // the values come from the devices, nothing keeps them from being absurdly large
+        let fill = quantum.saturating_add(self.headroom);
+        match (self.period_size, self.period_num) {
+            (Some(size), Some(num)) if size.saturating_mul(num) > 0 => fill.min(size.saturating_mul(num)),
+            _ => fill,
+        }
+    }
+
+    /// @returns the latency of the device in ms, when the graph runs at quantum and rate
+    pub fn ms(&self, quantum: u32, rate: u32) -> f32 {
+        let frames = self.buffered_frames(quantum).saturating_add(self.internal_frames);
+        frames_to_ms(frames, rate) + self.internal_ns as f32 / 1e6
+    }
+}
+
+/// The estimated latency of the active devices, in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyEstimate {
+    /// one quantum
+    pub graph_ms: f32,
+    /// the slowest input device, None if there is none
+    pub input_ms: Option<f32>,
+    /// the slowest output device, None if there is none
+    pub output_ms: Option<f32>,
+}
+
+impl LatencyEstimate {
+    /// @returns the latency of playback, from an application to the speakers
+    pub fn one_way_ms(&self) -> f32 {
+        self.graph_ms + self.output_ms.unwrap_or(0.0)
+    }
+
+    /// @returns the latency from the input to the output devices
+    pub fn round_trip_ms(&self) -> f32 {
+        self.input_ms.unwrap_or(0.0) + self.one_way_ms()
+    }
+}
+
+/// @returns the duration of frames at rate in ms, 0 if the rate is unknown
+pub fn frames_to_ms(frames: u32, rate: u32) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+    frames as f32 * 1000.0 / rate as f32
+}
+
+/// estimates the latency of devices when the graph runs at quantum and rate
+pub fn estimate(devices: &[DeviceLatency], quantum: u32, rate: u32) -> LatencyEstimate {
+    let slowest = |direction| devices.iter()
+        .filter(|d| d.direction == direction)
+        .map(|d| d.ms(quantum, rate))
+        .reduce(f32::max);
+    LatencyEstimate {
+        graph_ms: frames_to_ms(quantum, rate),
+        input_ms: slowest(Direction::Capture),
+        output_ms: slowest(Direction::Playback),
+    }
+}
+
+/// runs pw-dump and reads the latency settings of the active devices from it
+pub fn query() -> error::Result<Vec<DeviceLatency>> {
+    Ok(devices_from_dump(&pw_dump::dump()?))
+}
+
+/// @returns a stream of the latency settings of the active devices, or why they can't be read,
+/// polled every two seconds
+pub fn watch() -> impl Stream<Item = Result<Vec<DeviceLatency>, String>> {
+    process_stream::poll(Duration::from_secs(2), || query()
+        .map_err(|e| format!("Could not read the latency of the devices: {e}")))
+}
+
+pub fn devices_from_dump(objects: &[Value]) -> Vec<DeviceLatency> {
+    hardware::active_devices(objects)
+        .iter()
+        .flat_map(device_latency)
+        .collect()
+}
+
+/// @returns the latency settings of node, one per direction (a duplex device has both)
+fn device_latency(node: &Node) -> Vec<DeviceLatency> {
+    let directions: &[Direction] = match node.prop_str("media.class") {
+        Some("Audio/Source") => &[Direction::Capture],
+        Some("Audio/Sink") => &[Direction::Playback],
+        Some("Audio/Duplex") => &[Direction::Capture, Direction::Playback],
+        _ => &[],
+    };
+    let name = node.prop_str("node.description").or(node.prop_str("node.name")).unwrap_or_default();
+    directions.iter()
+        .map(|direction| DeviceLatency {
+            name: name.to_string(),
+            direction: *direction,
+            headroom: node.prop_u32("api.alsa.headroom").unwrap_or(0),
+            period_size: node.prop_u32("api.alsa.period-size"),
+            period_num: node.prop_u32("api.alsa.period-num"),
+            internal_frames: node.prop_u32("latency.internal.rate").unwrap_or(0),
+            internal_ns: node.prop_u32("latency.internal.ns").unwrap_or(0),
+        })
+        .collect()
+}
+
+/// parses a measured latency in ms as typed by the user, e.g. "12.5"
+pub fn parse_measured_ms(text: &str) -> Option<f32> {
+    text.trim().trim_end_matches("ms").trim().parse().ok().filter(|ms: &f32| *ms > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SCARLETT: &str = include_str!("../tests/fixtures/pw-dump/scarlett.json");
+
+    #[test]
+    fn estimates_round_trip_of_default_devices() {
+        let devices = devices_from_dump(&pw_dump::parse(SCARLETT).unwrap());
+        assert_eq!(devices.iter().map(|d| d.direction).collect::<Vec<_>>(),
+            vec![Direction::Playback, Direction::Capture]);
+        assert_eq!(devices[0].period_size, Some(1024));
+
+        // a graph cycle plus a quantum buffered in each device
+        let estimate = estimate(&devices, 256, 48000);
+        assert_eq!(estimate.round_trip_ms(), 16.0);
+        assert_eq!(estimate.one_way_ms(), frames_to_ms(512, 48000));
+
+        // the periods hold at most 2048 frames
+        let headroom = DeviceLatency { headroom: 1024, ..devices[0].clone() };
+        assert_eq!(headroom.buffered_frames(2048), 2048);
+
+        // absurd values reported by a driver don't overflow
+        let absurd = DeviceLatency { headroom: u32::MAX, period_size: Some(u32::MAX), internal_frames: u32::MAX,
+            ..devices[0].clone() };
+        assert_eq!(absurd.buffered_frames(256), u32::MAX);
+        assert!(absurd.ms(256, 48000) > 0.0);
+    }
+}
@@ -1,13 +1,49 @@
 // (C) Tim Lobner
 
-use iced::widget::{center, column, row, combo_box, text, pick_list, text_input, button};
-use iced::{Element, Theme};
+use iced::widget::{center, column, row, combo_box, text, pick_list, text_input, button, checkbox, scrollable,
+    container, Space};
+use iced::{window, Alignment, Element, Font, Subscription, Task, Theme};
 
-mod paths;
+mod storage;
 mod profile;
 mod sample_rate;
 mod buffer_size;
+mod settings_backend;
+mod pw_metadata;
+mod cli;
+mod process_stream;
+mod pw_dump;
+mod hardware;
+mod persist;
+mod clients;
+mod pw_top;
+mod wizard;
+mod app_rules;
+mod tray;
+mod dbus_service;
+mod profile_exchange;
+mod autostart;
+mod error;
+mod appearance;
+mod latency;
+use clients::AudioClient;
+use persist::PersistPlan;
+use hardware::{DriverClock, GraphState, HardwareLimits};
 use profile::LateProfile;
+use settings_backend::{SettingsBackend, PwMetadataBackend, FORCE_QUANTUM_KEY, FORCE_RATE_KEY, QUANTUM_KEY, RATE_KEY,
+    MIN_QUANTUM_KEY, MAX_QUANTUM_KEY, ALLOWED_RATES_KEY};
+use pw_metadata::MetadataLine;
+use pw_top::{DriverStats, XrunHistory};
+use wizard::{StabilityWizard, WizardStep};
+use app_rules::App;
+use tray::{TrayCommand, TrayStatus};
+use profile_exchange::OnConflict;
+use autostart::AutostartKind;
+use serde_helper::CustomTheme;
+use appearance::ColorScheme;
+use latency::{DeviceLatency, Direction};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 mod serde_helper;
 mod config;
 use config::LateConfig;
@@ -16,18 +52,217 @@ use config::LateConfig;
 #[derive(Debug, Clone)]
 enum Message {
     ThemeChanged(Theme),
+    /// switch between a light and a dark theme as the desktop prefers
+    ToggleFollowSystemTheme(bool),
+    LightThemeChanged(Theme),
+    DarkThemeChanged(Theme),
+    /// the desktop now prefers a light or dark theme
+    ColorSchemeChanged(ColorScheme),
     UpdateBufferSize(u32),
     UpdateSampleRate(u32),
     SaveProfile,
     DeleteProfile,
     UpdateProfile(String),
     UpdateProfileSaveName(String),
+    /// the settings metadata was changed, possibly by someone else
+    SettingsChanged(MetadataLine),
+    /// the driver of the graph, its clock or the active clients changed
+    GraphChanged(GraphState),
+    ToggleAdvanced,
+    UpdateMinQuantum(u32),
+    UpdateMaxQuantum(u32),
+    UpdateAllowedRatesText(String),
+    ApplyAllowedRates,
+    UpdateCustomBufferSize(String),
+    UpdateCustomSampleRate(String),
+    /// set the typed buffer size, if pipewire and the hardware allow it
+    ApplyCustomBufferSize,
+    ApplyCustomSampleRate,
+    UpdateFavouriteBufferSizesText(String),
+    ApplyFavouriteBufferSizes,
+    UpdateFavouriteSampleRatesText(String),
+    ApplyFavouriteSampleRates,
+    ToggleProfileAdvanced(bool),
+    /// show what making the active profile persistent would change
+    PreparePersist,
+    /// show what removing the persistent settings would change
+    PrepareRemovePersist,
+    ConfirmPersist,
+    CancelPersist,
+    /// execute the pending change despite running audio clients
+    ConfirmChange,
+    CancelChange,
+    UpdateFragileClientsText(String),
+    ApplyFragileClients,
+    /// one second of the revert countdown passed
+    RevertTick,
+    KeepSettings,
+    RevertSettings,
+    UpdateRevertTimeout(String),
+    ToggleMonitor,
+    ToggleLatency,
+    /// the latency settings of the active devices, read from pw-dump
+    LatencyDevicesChanged(Vec<DeviceLatency>),
+    UpdateMeasuredLatency(String),
+    /// pw-top reported the drivers of the graph
+    TopChanged(Vec<DriverStats>),
+    /// search the lowest buffer size without xruns
+    StartWizard,
+    /// one second of the wizard passed
+    WizardTick,
+    CancelWizard,
+    /// force the buffer size the wizard found
+    ApplyWizardResult,
+    UpdateWizardProfileName(String),
+    SaveWizardProfile,
+    CloseWizard,
+    UpdateWizardSoak(String),
+    /// the applications connected to pipewire changed
+    AppsChanged(Vec<App>),
+    ToggleMinimizeToTray(bool),
+    /// something was chosen in the tray menu
+    Tray(TrayCommand),
+    /// the window is about to be closed
+    CloseRequested,
+    /// ask where to export all (or only the selected) profiles to
+    ExportProfiles { all: bool },
+    ExportTo { all: bool, path: Option<PathBuf> },
+    /// ask which file to import
+    ImportProfiles,
+    ImportFrom(Option<PathBuf>),
+    UpdateOnConflict(OnConflict),
+    ToggleProfileManager,
+    EditProfileName(usize, String),
+    EditProfileBufferSize(usize, String),
+    EditProfileSampleRate(usize, String),
+    /// save the edited name and values of a profile
+    CommitProfile(usize),
+    /// move a profile from the first index to the second
+    MoveProfile(usize, usize),
+    DuplicateProfile(usize),
+    DeleteProfileAt(usize),
+    /// make the profile the default, None for no default
+    SetDefaultProfile(Option<usize>),
+    /// show an error which happened in the background, e.g. while watching the settings
+    Notify(String),
+    DismissNotification(usize),
+    /// edit the colors of the current theme
+    EditPalette,
+    UpdatePaletteName(String),
+    UpdatePaletteColor(usize, String),
+    ApplyPalette,
+    ClosePaletteEditor,
+    /// the profile to apply at login, None for the default profile
+    UpdateStartupProfile(Option<String>),
+    ToggleAutostart(bool),
+}
+
+/// A custom theme as edited in the palette editor. colors are typed as hex, e.g. #1e1e2e
+#[derive(Debug, Clone, PartialEq)]
+struct PaletteDraft {
+    name: String,
+    /// in the order of PALETTE_COLORS
+    colors: [String; 5],
+}
+
+static PALETTE_COLORS: [&str; 5] = ["Background", "Text", "Primary", "Success", "Danger"];
+
+impl PaletteDraft {
+    fn new(theme: &Theme) -> Self {
+        let palette = theme.palette();
+        Self {
+            // a built-in theme becomes the base of a new one
+            name: match theme {
+                Theme::Custom(_) => theme.to_string(),
+                _ => "Custom".to_string(),
+            },
+            colors: [palette.background, palette.text, palette.primary, palette.success, palette.danger]
+                .map(serde_helper::color_to_hex),
+        }
+    }
+
+    /// @returns the theme, or which color can't be parsed
+    fn parse(&self) -> Result<CustomTheme, String> {
+        if self.name.trim().is_empty() {
+            return Err("The theme needs a name".to_string());
+        }
+        let mut colors = [iced::Color::BLACK; 5];
+        for (i, hex) in self.colors.iter().enumerate() {
+            colors[i] = iced::Color::parse(hex.trim())
+                .ok_or(format!("{}: '{}' is not a color like #1e1e2e", PALETTE_COLORS[i], hex))?;
+        }
+        let [background, text, primary, success, danger] = colors;
+        Ok(CustomTheme {
+            name: self.name.clone(),
+            palette: iced::theme::Palette { background, text, primary, success, danger },
+        })
+    }
+}
+
+/// A profile as edited in the profile manager, before it is saved
+#[derive(Debug, Clone, PartialEq)]
+struct ProfileDraft {
+    /// the name of the profile as saved, to find the draft again when the profiles change
+    saved_name: String,
+    name: String,
+    buffer_size: String,
+    sample_rate: String,
+    /// whether anything was typed since the draft was made
+    edited: bool,
+}
+
+impl ProfileDraft {
+    fn new(profile: &LateProfile) -> Self {
+        Self {
+            saved_name: profile.name.clone(),
+            name: profile.name.clone(),
+            buffer_size: profile.buffer_size.to_string(),
+            sample_rate: profile.sample_rate.to_string(),
+            edited: false,
+        }
+    }
+
+    /// @returns whether saving the draft would change profile
+    fn changes(&self, profile: &LateProfile) -> bool {
+        let saved = ProfileDraft::new(profile);
+        (&self.name, &self.buffer_size, &self.sample_rate) != (&saved.name, &saved.buffer_size, &saved.sample_rate)
+    }
+}
+
+/// A profile applied because of an app rule
+struct AppRuleState {
+    profile: String,
+    /// the forced values before the profile was applied, restored once the app exits
+    buffer_size: Option<u32>,
+    sample_rate: Option<u32>,
+    /// min and max quantum before the profile was applied, if it changes them. 0 means unset
+    quantum_limits: Option<(u32, u32)>,
+    /// the allowed rates before the profile was applied as typed, if it changes them
+    allowed_rates: Option<String>,
+}
+
+/// The values to go back to, if the user doesn't keep a change in time
+struct RevertState {
+    buffer_size: Option<u32>,
+    sample_rate: Option<u32>,
+    /// seconds left until reverting
+    remaining: u32,
+}
+
+/// A change waiting to be confirmed, because audio clients are running which might crash
+struct PendingChange {
+    message: Message,
+    clients: Vec<AudioClient>,
 }
 
 /// The LateState is the state of the GUI. It encompasses the current buffer size
 /// and sampling rate, as well as the theme and all possible options
 struct LateState {
+    /// where config and profiles are saved, None if it can't be found, e.g. without a home
+    config_dir: Option<PathBuf>,
     config: LateConfig,
+    /// where buffer size and sample rate are read from and written to
+    backend: Box<dyn SettingsBackend>,
 
     buffer_sizes: combo_box::State<u32>,
     buffer_size: Option<u32>,
@@ -37,6 +272,91 @@ struct LateState {
     sample_rate: Option<u32>,
     // the text displayed when a sample rate is selected
     sr_text: String,
+    /// a buffer size or sample rate typed instead of chosen from the favourites
+    custom_buffer_size: String,
+    custom_sample_rate: String,
+    /// why the typed value can't be used
+    custom_value_error: Option<String>,
+    /// clock.quantum and clock.rate, which pipewire uses if nothing is forced
+    default_buffer_size: Option<u32>,
+    default_sample_rate: Option<u32>,
+    /// the clock of the driver the graph runs on, if it is known
+    driver: Option<DriverClock>,
+    /// the active audio clients, as of the last pw-dump
+    clients: Vec<AudioClient>,
+    /// what the hardware and pipewire allow, used to filter the combo boxes
+    limits: HardwareLimits,
+
+    /// whether the advanced section (quantum limits and allowed rates) is shown
+    show_advanced: bool,
+    min_quantums: combo_box::State<u32>,
+    min_quantum: Option<u32>,
+    max_quantums: combo_box::State<u32>,
+    max_quantum: Option<u32>,
+    /// the allowed rates as typed by the user, e.g. "44100 48000"
+    allowed_rates_text: String,
+    /// the favourites as typed by the user, e.g. "32 64 128"
+    favourite_buffer_sizes_text: String,
+    favourite_sample_rates_text: String,
+    /// whether saved profiles carry the advanced values, too
+    profile_with_advanced: bool,
+    /// the pending change to the pipewire drop-in, shown as a diff until confirmed
+    persist_plan: Option<PersistPlan>,
+    /// result of the last persist action
+    persist_status: Option<String>,
+    /// shown as confirmation dialog while audio clients are running
+    pending_change: Option<PendingChange>,
+    /// set while executing a confirmed change, so nested updates don't ask again
+    change_confirmed: bool,
+    /// the fragile clients as typed by the user, comma separated
+    fragile_clients_text: String,
+    /// running countdown after a change, shown as "keep these settings?" banner
+    revert: Option<RevertState>,
+    /// set while changing values automatically (reverts, app rules), so no countdown starts
+    automatic_change: bool,
+    /// whether the window is hidden in the tray
+    window_hidden: bool,
+    /// what the tray shows, brought up to date after every message
+    tray_status: Arc<Mutex<TrayStatus>>,
+    /// whether the monitor is shown, pw-top only runs while it is
+    show_monitor: bool,
+    /// whether the latency breakdown is shown
+    show_latency: bool,
+    latency_devices: Vec<DeviceLatency>,
+    /// the round trip latency as measured by the user, in ms
+    measured_latency: String,
+    /// the drivers as last reported by pw-top
+    top_drivers: Vec<DriverStats>,
+    /// xruns since the last settings change
+    xruns: XrunHistory,
+    /// the running or finished buffer size wizard
+    wizard: Option<StabilityWizard>,
+    /// the name to save the wizard's result as
+    wizard_profile_name: String,
+    /// the profile applied by an app rule, if any
+    app_rule: Option<AppRuleState>,
+    /// started with --daemon, i.e. the tray icon is shown regardless of the config
+    daemon: bool,
+    /// what importing does with profiles whose names are taken
+    on_conflict: OnConflict,
+    /// result of the last import or export
+    exchange_status: Option<String>,
+    /// whether the profile manager is shown
+    show_profile_manager: bool,
+    /// one per profile, in the same order
+    profile_drafts: Vec<ProfileDraft>,
+    /// result of the last change to the profiles, e.g. a name that is taken
+    profile_status: Option<String>,
+    /// how late is started at login to apply the startup profile, if it is
+    autostart: Option<AutostartKind>,
+    /// result of the last change to the autostart
+    autostart_status: Option<String>,
+    /// errors shown until the user dismisses them
+    notifications: Vec<String>,
+    /// the open palette editor, previewed as theme as long as all colors are valid
+    palette_draft: Option<PaletteDraft>,
+    /// what the desktop prefers, see config.follow_system_theme
+    color_scheme: ColorScheme,
     /// name of the current profile, if any
     profile: Option<String>,
     profiles_names: combo_box::State<String>,
@@ -46,20 +366,91 @@ struct LateState {
 
 impl LateState {
 
-    fn new(config: LateConfig, profiles: Vec<LateProfile>) -> Self {
+    fn new(config_dir: Option<PathBuf>, config: LateConfig, profiles: Vec<LateProfile>,
+        backend: Box<dyn SettingsBackend>, limits: HardwareLimits) -> Self {
+        let mut notifications = Vec::new();
+        let mut read = |what: &str, value: error::Result<Option<u32>>| value.unwrap_or_else(|e| {
+            notifications.push(format!("Could not read the {what}: {e}"));
+            None
+        });
+        let buffer_size = read("buffer size", buffer_size::get_current_buffer_size(backend.as_ref()));
+        let sample_rate = read("sample rate", sample_rate::get_current_sample_rate(backend.as_ref()));
+        // the defaults and limits most likely fail for the same reason, which is shown once
+        fn or_default<T: Default>(value: error::Result<T>, first_error: &mut Option<error::LateError>) -> T {
+            value.unwrap_or_else(|e| {
+                first_error.get_or_insert(e);
+                T::default()
+            })
+        }
+        let mut error = None;
+        let (min_quantum, max_quantum) = or_default(buffer_size::get_quantum_limits(backend.as_ref()), &mut error);
+        let allowed_rates = or_default(sample_rate::get_allowed_sample_rates(backend.as_ref()), &mut error);
+        let default_buffer_size = or_default(buffer_size::get_default_buffer_size(backend.as_ref()), &mut error);
+        let default_sample_rate = or_default(sample_rate::get_default_sample_rate(backend.as_ref()), &mut error);
+        if let Some(e) = error {
+            notifications.push(format!("Could not read the defaults and limits of pipewire: {e}"));
+        }
+        let buffer_sizes = buffer_size::get_supported_buffer_sizes(&limits, &config.favourite_buffer_sizes);
+        let sample_rates = sample_rate::get_supported_sample_rates(&limits, &config.favourite_sample_rates);
         Self {
+            config_dir,
+            fragile_clients_text: config.fragile_clients.join(", "),
+            favourite_buffer_sizes_text: values_to_text(&config.favourite_buffer_sizes),
+            favourite_sample_rates_text: values_to_text(&config.favourite_sample_rates),
             config,
-            buffer_sizes: combo_box::State::new(buffer_size::get_available_buffer_sizes()),
-            buffer_size: buffer_size::get_current_buffer_size(),
+            buffer_sizes: combo_box::State::new(buffer_sizes),
+            buffer_size,
             bs_text: String::new(),
-            sample_rates: combo_box::State::new(sample_rate::get_available_sample_rates()),
-            sample_rate: sample_rate::get_current_sample_rate(),
+            sample_rates: combo_box::State::new(sample_rates),
+            sample_rate,
             sr_text: String::new(),
+            custom_buffer_size: String::new(),
+            custom_sample_rate: String::new(),
+            custom_value_error: None,
+            default_buffer_size,
+            default_sample_rate,
+            driver: None,
+            clients: Vec::new(),
+            limits,
+            show_advanced: false,
+            min_quantums: combo_box::State::new(buffer_size::get_available_buffer_sizes()),
+            min_quantum,
+            max_quantums: combo_box::State::new(buffer_size::get_available_buffer_sizes()),
+            max_quantum,
+            allowed_rates_text: allowed_rates.map(|r| values_to_text(&r)).unwrap_or_default(),
+            profile_with_advanced: false,
+            persist_plan: None,
+            persist_status: None,
+            pending_change: None,
+            change_confirmed: false,
+            revert: None,
+            automatic_change: false,
+            window_hidden: false,
+            tray_status: Arc::default(),
+            show_monitor: false,
+            show_latency: false,
+            latency_devices: Vec::new(),
+            measured_latency: String::new(),
+            top_drivers: Vec::new(),
+            xruns: XrunHistory::default(),
+            wizard: None,
+            wizard_profile_name: String::new(),
+            app_rule: None,
+            daemon: false,
+            on_conflict: OnConflict::default(),
+            exchange_status: None,
+            show_profile_manager: false,
+            profile_drafts: profiles.iter().map(ProfileDraft::new).collect(),
+            profile_status: None,
+            autostart: autostart::installed(),
+            autostart_status: None,
+            notifications,
+            palette_draft: None,
+            color_scheme: ColorScheme::default(),
             profiles_names: combo_box::State::new(profile::get_profile_names(&profiles)),
-            profile: profile::get_current_if_any(&profiles, 
-                sample_rate::get_current_sample_rate(),
-                buffer_size::get_current_buffer_size()),
+            profile: profile::get_current_if_any(&profiles, sample_rate, buffer_size),
             profiles,
+            backend,
             profile_save_name: "".to_string(),
         }
     }
@@ -68,9 +459,29 @@ impl LateState {
         match message {
             Message::ThemeChanged(theme) => {
                 self.config.theme = theme;
-                config::save_config(&self.config);
+                self.save_config();
+            }
+            Message::ToggleFollowSystemTheme(follow) => {
+                self.config.follow_system_theme = follow;
+                self.save_config();
+            }
+            Message::LightThemeChanged(theme) => {
+                self.config.light_theme = theme;
+                self.save_config();
+            }
+            Message::DarkThemeChanged(theme) => {
+                self.config.dark_theme = theme;
+                self.save_config();
+            }
+            Message::ColorSchemeChanged(scheme) => {
+                self.color_scheme = scheme;
             }
             Message::UpdateBufferSize(buf_size) => {
+                if self.hold_for_confirmation(Message::UpdateBufferSize(buf_size)) {
+                    return;
+                }
+                self.start_revert_countdown();
+                self.xruns.clear();
                 self.buffer_size = Some(buf_size);
                 self.bs_text = 
                     buf_size.to_string()
@@ -79,23 +490,43 @@ impl LateState {
                     + "ms)";
 
                 // actually execute the change
-                buffer_size::set_buffer_size(buf_size);
+                if let Err(e) = buffer_size::set_buffer_size(self.backend.as_ref(), buf_size) {
+                    self.notify(format!("Could not set the buffer size: {e}"));
+                }
             }
             Message::UpdateSampleRate(rate) => {
+                if self.hold_for_confirmation(Message::UpdateSampleRate(rate)) {
+                    return;
+                }
+                self.start_revert_countdown();
+                self.xruns.clear();
                 self.sample_rate = Some(rate);
                 self.sr_text = 
                     rate.to_string()
                     + " Hz";
 
                 // actually execute the change
-                sample_rate::set_sample_rate(rate);
+                if let Err(e) = sample_rate::set_sample_rate(self.backend.as_ref(), rate) {
+                    self.notify(format!("Could not set the sample rate: {e}"));
+                }
             }
             Message::UpdateProfile(pro) => {
                 let chosen = profile::choose_profile(&self.profiles, &pro);
-                if chosen.is_some() {
-                    let profile = chosen.unwrap();
+                if let Some(profile) = chosen {
+                    if self.hold_for_confirmation(Message::UpdateProfile(pro.clone())) {
+                        return;
+                    }
+                    // one confirmation covers all values of the profile
+                    let was_confirmed = std::mem::replace(&mut self.change_confirmed, true);
+                    // limits first, so that the forced values are not clamped to the old ones
+                    self.set_quantum_limits(profile.min_quantum, profile.max_quantum);
+                    if let Some(rates) = &profile.allowed_rates {
+                        self.update(Message::UpdateAllowedRatesText(values_to_text(rates)));
+                        self.update(Message::ApplyAllowedRates);
+                    }
                     self.update(Message::UpdateSampleRate(profile.sample_rate));
                     self.update(Message::UpdateBufferSize(profile.buffer_size));
+                    self.change_confirmed = was_confirmed;
                     self.profile = Some(profile.name.clone());
                 } else if pro.is_empty() {
                     // most likely a delete has happened
@@ -103,27 +534,36 @@ impl LateState {
                 }
             }
             Message::DeleteProfile => {
-                let profile_name = self.profile.clone().unwrap();
-                profile::remove_profile(&mut self.profiles, &profile_name);
-                self.profiles_names = combo_box::State::new(profile::get_profile_names(&self.profiles));
-                // saving writes the entire file new. since the profile is deleted from the vector,
-                // we save here in order to get it out of the profiles file
-                profile::save_profiles(&self.profiles);
+                let Some(profile_name) = self.profile.clone() else {
+                    return;
+                };
+                self.change_profiles(|profiles| {
+                    profile::remove_profile(profiles, &profile_name);
+                    Ok(())
+                });
                 // finally set the profile to empty, since the previously deleted profile must not
                 // be enabled anymore, but we have no better guess of what to choose (and we don't
                 // want to change the profile here)
                 self.update(Message::UpdateProfile("".to_string()));
             }
             Message::SaveProfile => {
-                let new_profile = LateProfile {
+                let mut new_profile = LateProfile {
                     name: self.profile_save_name.clone(),
                     sample_rate: self.sample_rate.unwrap_or(0),
                     buffer_size: self.buffer_size.unwrap_or(0),
+                    ..Default::default()
                 };
-                self.profiles.push(new_profile);
-
-                self.profiles_names = combo_box::State::new(profile::get_profile_names(&self.profiles));
-                profile::save_profiles(&self.profiles);
+                if self.profile_with_advanced {
+                    // unset limits are saved as 0, so that applying the profile resets them
+                    new_profile.min_quantum = Some(self.min_quantum.unwrap_or(0));
+                    new_profile.max_quantum = Some(self.max_quantum.unwrap_or(0));
+                    new_profile.allowed_rates = Some(text_to_values(&self.allowed_rates_text));
+                }
+                // saving under an existing name replaces that profile
+                if self.change_profiles(|profiles| Ok(profile::upsert_profile(profiles, new_profile)?)).is_none() {
+                    return;
+                }
+                self.profile_status = None;
 
                 // Update the profile as well in order to write the saved name into the profile
                 // combo box
@@ -132,10 +572,853 @@ impl LateState {
             Message::UpdateProfileSaveName(pro) => {
                 self.profile_save_name = pro;
             }
+            Message::SettingsChanged(line) => {
+                // only display the new values, they are already set
+                match line {
+                    MetadataLine::Update(entry) if entry.id == 0 => {
+                        self.external_change(&entry.key, Some(&entry.value));
+                    }
+                    MetadataLine::Remove { id: 0, key: Some(key) } => {
+                        self.external_change(&key, None);
+                    }
+                    MetadataLine::Remove { id: 0, key: None } => {
+                        for key in [FORCE_QUANTUM_KEY, FORCE_RATE_KEY, QUANTUM_KEY, RATE_KEY,
+                            MIN_QUANTUM_KEY, MAX_QUANTUM_KEY, ALLOWED_RATES_KEY] {
+                            self.external_change(key, None);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Message::GraphChanged(graph) => {
+                self.driver = graph.driver;
+                self.clients = graph.clients;
+            }
+            Message::ToggleAdvanced => {
+                self.show_advanced = !self.show_advanced;
+            }
+            Message::UpdateMinQuantum(size) => {
+                self.set_quantum_limits(Some(size), None);
+            }
+            Message::UpdateMaxQuantum(size) => {
+                // 0 unsets the max quantum
+                self.set_quantum_limits(None, Some(size));
+            }
+            Message::UpdateAllowedRatesText(rates) => {
+                self.allowed_rates_text = rates;
+            }
+            Message::ApplyAllowedRates => {
+                let rates = text_to_values(&self.allowed_rates_text);
+                self.set_allowed_rates(&rates);
+                if let Err(e) = sample_rate::set_allowed_sample_rates(self.backend.as_ref(), &rates) {
+                    self.notify(format!("Could not set the allowed sample rates: {e}"));
+                }
+            }
+            Message::UpdateCustomBufferSize(size) => {
+                self.custom_buffer_size = size;
+                self.custom_value_error = None;
+            }
+            Message::UpdateCustomSampleRate(rate) => {
+                self.custom_sample_rate = rate;
+                self.custom_value_error = None;
+            }
+            Message::ApplyCustomBufferSize => {
+                match buffer_size::parse_buffer_size(&self.custom_buffer_size, &self.limits) {
+                    Ok(size) => {
+                        self.custom_buffer_size.clear();
+                        self.update(Message::UpdateBufferSize(size));
+                    }
+                    Err(e) => self.custom_value_error = Some(format!("Buffer size {}: {}",
+                        self.custom_buffer_size.trim(), e)),
+                }
+            }
+            Message::ApplyCustomSampleRate => {
+                match sample_rate::parse_sample_rate(&self.custom_sample_rate, &self.limits) {
+                    Ok(rate) => {
+                        self.custom_sample_rate.clear();
+                        self.update(Message::UpdateSampleRate(rate));
+                    }
+                    Err(e) => self.custom_value_error = Some(format!("Sample rate {}: {}",
+                        self.custom_sample_rate.trim(), e)),
+                }
+            }
+            Message::UpdateFavouriteBufferSizesText(sizes) => {
+                self.favourite_buffer_sizes_text = sizes;
+            }
+            Message::ApplyFavouriteBufferSizes => {
+                self.config.favourite_buffer_sizes = text_to_values(&self.favourite_buffer_sizes_text);
+                self.refresh_buffer_sizes();
+                self.save_config();
+            }
+            Message::UpdateFavouriteSampleRatesText(rates) => {
+                self.favourite_sample_rates_text = rates;
+            }
+            Message::ApplyFavouriteSampleRates => {
+                self.config.favourite_sample_rates = text_to_values(&self.favourite_sample_rates_text);
+                self.refresh_sample_rates();
+                self.save_config();
+            }
+            Message::ToggleProfileAdvanced(with_advanced) => {
+                self.profile_with_advanced = with_advanced;
+            }
+            Message::PreparePersist => {
+                let chosen = self.profile.as_ref()
+                    .and_then(|name| profile::choose_profile(&self.profiles, name));
+                if let Some(chosen) = chosen {
+                    self.set_persist_plan(persist::plan_write(&chosen));
+                }
+            }
+            Message::PrepareRemovePersist => {
+                self.set_persist_plan(persist::plan_remove());
+            }
+            Message::ConfirmPersist => {
+                if let Some(plan) = self.persist_plan.take() {
+                    self.persist_status = Some(match plan.apply() {
+                        Ok(()) => "Done, the changes take effect after pipewire restarts".to_string(),
+                        Err(e) => format!("Could not write {}: {}", plan.path.display(), e),
+                    });
+                }
+            }
+            Message::CancelPersist => {
+                self.persist_plan = None;
+                self.persist_status = None;
+            }
+            Message::ConfirmChange => {
+                if let Some(pending) = self.pending_change.take() {
+                    self.change_confirmed = true;
+                    self.update(pending.message);
+                    self.change_confirmed = false;
+                }
+            }
+            Message::CancelChange => {
+                self.pending_change = None;
+            }
+            Message::UpdateFragileClientsText(clients) => {
+                self.fragile_clients_text = clients;
+            }
+            Message::ApplyFragileClients => {
+                self.config.fragile_clients = self.fragile_clients_text
+                    .split(',')
+                    .map(|c| c.trim().to_string())
+                    .filter(|c| !c.is_empty())
+                    .collect();
+                self.save_config();
+            }
+            Message::RevertTick => {
+                if let Some(revert) = &mut self.revert {
+                    revert.remaining = revert.remaining.saturating_sub(1);
+                    if revert.remaining == 0 {
+                        self.update(Message::RevertSettings);
+                    }
+                }
+            }
+            Message::KeepSettings => {
+                self.revert = None;
+            }
+            Message::RevertSettings => {
+                if let Some(revert) = self.revert.take() {
+                    // going back must not ask for confirmation or start another countdown
+                    self.automatic_change = true;
+                    self.change_confirmed = true;
+                    self.update(Message::UpdateSampleRate(revert.sample_rate.unwrap_or(0)));
+                    self.update(Message::UpdateBufferSize(revert.buffer_size.unwrap_or(0)));
+                    self.change_confirmed = false;
+                    self.automatic_change = false;
+                    self.profile = profile::get_current_if_any(&self.profiles, self.sample_rate, self.buffer_size);
+                }
+            }
+            Message::UpdateRevertTimeout(secs) => {
+                if let Ok(secs) = secs.parse() {
+                    self.config.revert_timeout_secs = secs;
+                    self.save_config();
+                } else if secs.is_empty() {
+                    self.config.revert_timeout_secs = 0;
+                    self.save_config();
+                }
+            }
+            Message::ToggleMonitor => {
+                self.show_monitor = !self.show_monitor;
+                // pw-top starts counting from 0 again
+                self.xruns = XrunHistory::default();
+                self.top_drivers.clear();
+            }
+            Message::ToggleLatency => {
+                self.show_latency = !self.show_latency;
+            }
+            Message::LatencyDevicesChanged(devices) => {
+                self.latency_devices = devices;
+            }
+            Message::UpdateMeasuredLatency(ms) => {
+                self.measured_latency = ms;
+            }
+            Message::TopChanged(drivers) => {
+                self.xruns.push(&drivers);
+                self.top_drivers = drivers;
+                if let (Some(wizard), Some(new)) = (&mut self.wizard, self.xruns.counts().back()) {
+                    wizard.add_xruns(*new);
+                }
+            }
+            Message::StartWizard => {
+                if self.hold_for_confirmation(Message::StartWizard) {
+                    return;
+                }
+                // the wizard restores the buffer size it started with, which must not be one still to be kept
+                self.update(Message::RevertSettings);
+                let candidates = wizard::candidates(&self.limits, self.running_buffer_size());
+                let wizard = StabilityWizard::new(candidates, self.config.wizard_soak_secs, self.buffer_size);
+                if let Some(wizard) = wizard {
+                    let first = wizard.current();
+                    self.wizard = Some(wizard);
+                    self.update(Message::UpdateBufferSize(first));
+                }
+            }
+            Message::WizardTick => {
+                let Some(wizard) = &mut self.wizard else {
+                    return;
+                };
+                let was_done = wizard.is_done();
+                match wizard.tick() {
+                    WizardStep::Wait => {}
+                    WizardStep::Set(size) => self.update(Message::UpdateBufferSize(size)),
+                    WizardStep::Done if !was_done => self.restore_after_wizard(),
+                    WizardStep::Done => {}
+                }
+            }
+            Message::CancelWizard => {
+                if self.wizard.as_ref().is_some_and(|w| !w.is_done()) {
+                    self.restore_after_wizard();
+                }
+                self.wizard = None;
+            }
+            Message::ApplyWizardResult => {
+                let recommendation = self.wizard.take().and_then(|w| w.recommendation());
+                if let Some(size) = recommendation {
+                    self.update(Message::UpdateBufferSize(size));
+                }
+            }
+            Message::UpdateWizardProfileName(name) => {
+                self.wizard_profile_name = name;
+            }
+            Message::SaveWizardProfile => {
+                let recommendation = self.wizard.as_ref().and_then(|w| w.recommendation());
+                if let Some(size) = recommendation {
+                    let new_profile = LateProfile {
+                        name: self.wizard_profile_name.clone(),
+                        buffer_size: size,
+                        sample_rate: self.running_sample_rate().unwrap_or(0),
+                        ..Default::default()
+                    };
+                    if self.change_profiles(|profiles| Ok(profile::upsert_profile(profiles, new_profile)?)).is_some() {
+                        self.wizard = None;
+                    }
+                }
+            }
+            Message::CloseWizard => {
+                self.wizard = None;
+            }
+            Message::UpdateWizardSoak(secs) => {
+                if let Ok(secs) = secs.parse() {
+                    self.config.wizard_soak_secs = secs;
+                    self.save_config();
+                }
+            }
+            Message::AppsChanged(apps) => {
+                let wanted = app_rules::active_rule(&self.config.app_rules, &apps)
+                    .map(|rule| rule.profile.clone());
+                if wanted == self.app_rule.as_ref().map(|r| r.profile.clone()) {
+                    return;
+                }
+                // the rule was set up by the user, so it is applied and kept without asking
+                self.automatic_change = true;
+                let was_confirmed = std::mem::replace(&mut self.change_confirmed, true);
+                match wanted {
+                    Some(profile) => {
+                        let chosen = profile::choose_profile(&self.profiles, &profile);
+                        let quantum_limits = chosen.as_ref()
+                            .filter(|p| p.min_quantum.is_some() || p.max_quantum.is_some())
+                            .map(|_| (self.min_quantum.unwrap_or(0), self.max_quantum.unwrap_or(0)));
+                        let allowed_rates = chosen.as_ref()
+                            .filter(|p| p.allowed_rates.is_some())
+                            .map(|_| self.allowed_rates_text.clone());
+                        // when switching between rules, the values from before the first one are restored
+                        self.app_rule = Some(match self.app_rule.take() {
+                            Some(previous) => AppRuleState {
+                                profile: profile.clone(),
+                                quantum_limits: previous.quantum_limits.or(quantum_limits),
+                                allowed_rates: previous.allowed_rates.or(allowed_rates),
+                                ..previous
+                            },
+                            None => AppRuleState {
+                                profile: profile.clone(),
+                                buffer_size: self.buffer_size,
+                                sample_rate: self.sample_rate,
+                                quantum_limits,
+                                allowed_rates,
+                            },
+                        });
+                        self.update(Message::UpdateProfile(profile));
+                    }
+                    None => {
+                        if let Some(previous) = self.app_rule.take() {
+                            // limits first, the same order the profile was applied in
+                            if let Some((min, max)) = previous.quantum_limits {
+                                self.set_quantum_limits(Some(min), Some(max));
+                            }
+                            if let Some(rates) = previous.allowed_rates {
+                                self.update(Message::UpdateAllowedRatesText(rates));
+                                self.update(Message::ApplyAllowedRates);
+                            }
+                            self.update(Message::UpdateSampleRate(previous.sample_rate.unwrap_or(0)));
+                            self.update(Message::UpdateBufferSize(previous.buffer_size.unwrap_or(0)));
+                            self.profile = profile::get_current_if_any(&self.profiles, self.sample_rate, self.buffer_size);
+                        }
+                    }
+                }
+                self.change_confirmed = was_confirmed;
+                self.automatic_change = false;
+            }
+            Message::ToggleMinimizeToTray(minimize) => {
+                self.config.minimize_to_tray = minimize;
+                self.save_config();
+            }
+            Message::ExportTo { all, path: Some(path) } => {
+                let profiles = if all {
+                    self.profiles.clone()
+                } else {
+                    self.profile.iter()
+                        .filter_map(|name| profile::choose_profile(&self.profiles, name))
+                        .collect()
+                };
+                self.exchange_status = Some(match profile_exchange::export(&profiles, &path) {
+                    Ok(()) => format!("Exported {} profiles to {}", profiles.len(), path.display()),
+                    Err(e) => format!("Could not write {}: {}", path.display(), e),
+                });
+            }
+            Message::ImportFrom(Some(path)) => {
+                let on_conflict = self.on_conflict;
+                self.exchange_status = match profile_exchange::read(&path) {
+                    Ok(imported) => self.change_profiles(|profiles| Ok(profile_exchange::merge(profiles, imported, on_conflict)))
+                        .map(|summary| format!("Imported: {}", summary)),
+                    Err(e) => Some(format!("Could not read {}: {}", path.display(), e)),
+                };
+            }
+            // the dialog was cancelled
+            Message::ExportTo { path: None, .. } | Message::ImportFrom(None) => {}
+            Message::UpdateOnConflict(on_conflict) => {
+                self.on_conflict = on_conflict;
+            }
+            Message::ToggleProfileManager => {
+                self.show_profile_manager = !self.show_profile_manager;
+                // unsaved edits are dropped
+                self.profile_drafts = self.profiles.iter().map(ProfileDraft::new).collect();
+                self.profile_status = None;
+            }
+            Message::EditProfileName(i, name) => {
+                if let Some(draft) = self.profile_drafts.get_mut(i) {
+                    draft.name = name;
+                    draft.edited = true;
+                }
+            }
+            Message::EditProfileBufferSize(i, size) => {
+                if let Some(draft) = self.profile_drafts.get_mut(i) {
+                    draft.buffer_size = size;
+                    draft.edited = true;
+                }
+            }
+            Message::EditProfileSampleRate(i, rate) => {
+                if let Some(draft) = self.profile_drafts.get_mut(i) {
+                    draft.sample_rate = rate;
+                    draft.edited = true;
+                }
+            }
+            Message::CommitProfile(i) => {
+                let Some(draft) = self.profile_drafts.get(i).cloned() else {
+                    return;
+                };
+                let (Ok(buffer_size), Ok(sample_rate)) = (draft.buffer_size.parse(), draft.sample_rate.parse()) else {
+                    self.profile_status = Some(format!("'{}' needs numbers as buffer size and sample rate", draft.name));
+                    return;
+                };
+                let old_name = draft.saved_name.clone();
+                let committed = self.change_profiles(|profiles| {
+                    let i = profile::position(profiles, &old_name)?;
+                    profile::rename_profile(profiles, i, &draft.name)?;
+                    profiles[i].buffer_size = buffer_size;
+                    profiles[i].sample_rate = sample_rate;
+                    Ok(i)
+                });
+                let Some(i) = committed else {
+                    return;
+                };
+                if self.profile.as_ref() == Some(&old_name) {
+                    self.profile = Some(draft.name.clone());
+                }
+                let mut config_changed = false;
+                if self.config.startup_profile.as_ref() == Some(&old_name) {
+                    self.config.startup_profile = Some(draft.name.clone());
+                    config_changed = true;
+                }
+                for rule in self.config.app_rules.iter_mut().filter(|rule| rule.profile == old_name) {
+                    rule.profile = draft.name.clone();
+                    config_changed = true;
+                }
+                if config_changed {
+                    self.save_config();
+                }
+                self.profile_status = Some(format!("Saved '{}'", draft.name));
+                self.profile_drafts[i] = ProfileDraft::new(&self.profiles[i]);
+            }
+            // the rows are found by name, the saved profiles may have changed since they were shown.
+            // a row which is gone already (e.g. clicked twice before the view caught up) is ignored
+            Message::MoveProfile(i, to) => {
+                let Some(name) = self.profiles.get(i).map(|p| p.name.clone()) else {
+                    return;
+                };
+                self.change_profiles(|profiles| {
+                    let i = profile::position(profiles, &name)?;
+                    profile::move_profile(profiles, i, to.min(profiles.len() - 1));
+                    Ok(())
+                });
+            }
+            Message::DuplicateProfile(i) => {
+                let Some(name) = self.profiles.get(i).map(|p| p.name.clone()) else {
+                    return;
+                };
+                let copy = self.change_profiles(|profiles| {
+                    let copy = profile::duplicate_profile(profiles, profile::position(profiles, &name)?);
+                    Ok(profiles[copy].name.clone())
+                });
+                if let Some(copy) = copy {
+                    self.profile_status = Some(format!("Added '{}'", copy));
+                }
+            }
+            Message::DeleteProfileAt(i) => {
+                let Some(name) = self.profiles.get(i).map(|p| p.name.clone()) else {
+                    return;
+                };
+                if self.profile.as_ref() == Some(&name) {
+                    self.profile = None;
+                }
+                let removed = self.change_profiles(|profiles| Ok(profiles.remove(profile::position(profiles, &name)?)));
+                if let Some(removed) = removed {
+                    self.profile_status = Some(format!("Deleted '{}'", removed.name));
+                }
+            }
+            Message::SetDefaultProfile(index) => {
+                let name = match index {
+                    Some(i) => match self.profiles.get(i) {
+                        Some(profile) => Some(profile.name.clone()),
+                        None => return,
+                    },
+                    None => None,
+                };
+                self.change_profiles(|profiles| {
+                    let index = name.map(|name| profile::position(profiles, &name)).transpose()?;
+                    profile::set_default(profiles, index);
+                    Ok(())
+                });
+            }
+            Message::Notify(message) => self.notify(message),
+            Message::DismissNotification(i) => {
+                if i < self.notifications.len() {
+                    self.notifications.remove(i);
+                }
+            }
+            Message::EditPalette => {
+                self.palette_draft = Some(PaletteDraft::new(&appearance::theme_for(&self.config, self.color_scheme)));
+            }
+            Message::UpdatePaletteName(name) => {
+                if let Some(draft) = &mut self.palette_draft {
+                    draft.name = name;
+                }
+            }
+            Message::UpdatePaletteColor(i, hex) => {
+                if let Some(color) = self.palette_draft.as_mut().and_then(|d| d.colors.get_mut(i)) {
+                    *color = hex;
+                }
+            }
+            Message::ApplyPalette => {
+                if let Some(Ok(custom)) = self.palette_draft.as_ref().map(PaletteDraft::parse) {
+                    *self.chosen_theme_mut() = custom.to_theme();
+                    self.config.custom_theme = Some(custom);
+                    self.palette_draft = None;
+                    self.save_config();
+                }
+            }
+            Message::ClosePaletteEditor => {
+                self.palette_draft = None;
+            }
+            Message::UpdateStartupProfile(name) => {
+                self.config.startup_profile = name;
+                self.save_config();
+            }
+            Message::ToggleAutostart(enabled) => {
+                let result = if enabled {
+                    autostart::install(AutostartKind::Desktop).map(|path| format!("Wrote {}", path.display()))
+                } else {
+                    autostart::remove().map(|_| "Nothing is applied at login anymore".to_string())
+                };
+                self.autostart_status = Some(result.unwrap_or_else(|e| format!("Could not change autostart: {e}")));
+                self.autostart = autostart::installed();
+            }
+            // these need a Task, see handle
+            Message::Tray(_) | Message::CloseRequested
+                | Message::ExportProfiles { .. } | Message::ImportProfiles => {}
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    /// handles the messages which need to talk to the window, all others go to update
+    fn handle(&mut self, message: Message) -> Task<Message> {
+        let task = match message {
+            Message::Tray(TrayCommand::ShowWindow) => {
+                self.window_hidden = false;
+                show_window()
+            }
+            Message::Tray(TrayCommand::ApplyProfile(profile)) => {
+                // nobody could keep the change in a hidden window, so it must not be reverted
+                self.automatic_change = self.window_hidden;
+                self.update(Message::UpdateProfile(profile));
+                self.automatic_change = false;
+                // the confirmation can only be answered in the window
+                if self.pending_change.is_some() {
+                    self.window_hidden = false;
+                    show_window()
+                } else {
+                    Task::none()
+                }
+            }
+            Message::Tray(TrayCommand::Quit) => iced::exit(),
+            Message::CloseRequested if self.has_tray() => {
+                self.window_hidden = true;
+                window::get_oldest().and_then(|id| window::change_mode(id, window::Mode::Hidden))
+            }
+            Message::CloseRequested => iced::exit(),
+            Message::ExportProfiles { all } => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .add_filter("Profiles", &["json", "toml"])
+                    .set_file_name("late_profiles.json")
+                    .save_file();
+                Task::perform(dialog, move |file| Message::ExportTo {
+                    all,
+                    path: file.map(|f| f.path().to_path_buf()),
+                })
+            }
+            Message::ImportProfiles => {
+                let dialog = rfd::AsyncFileDialog::new()
+                    .add_filter("Profiles", &["json", "toml"])
+                    .pick_file();
+                Task::perform(dialog, |file| Message::ImportFrom(file.map(|f| f.path().to_path_buf())))
+            }
+            message => {
+                self.update(message);
+                Task::none()
+            }
+        };
+        self.update_tray_status();
+        task
+    }
+
+    /// shows the running values and the profiles in the tray
+    fn update_tray_status(&self) {
+        *self.tray_status.lock().unwrap() = TrayStatus {
+            quantum: self.running_buffer_size(),
+            rate: self.running_sample_rate(),
+            profiles: profile::get_profile_names(&self.profiles),
+            profile: self.profile.clone(),
+        };
+    }
+
+    fn has_tray(&self) -> bool {
+        self.daemon || self.config.minimize_to_tray
+    }
+
+    /// shows message until it is dismissed. a message which is shown already isn't repeated
+    fn notify(&mut self, message: String) {
+        if !self.notifications.contains(&message) {
+            self.notifications.push(message);
+        }
+    }
+
+    fn save_config(&mut self) {
+        let saved = match &self.config_dir {
+            Some(dir) => config::save_config_in(dir, &self.config),
+            None => Err(error::LateError::NoHome),
+        };
+        if let Err(e) = saved {
+            self.notify(format!("Could not save the config: {e}"));
+        }
+    }
+
+    /// changes the saved profiles and brings everything showing them up to date.
+    /// the change is made to the profiles as saved right now, so that changes made meanwhile by the CLI,
+    /// D-Bus or another window are kept
+    /// @returns what change returned, None if it failed, which is shown in the profile status or as notification
+    fn change_profiles<T>(&mut self, change: impl FnOnce(&mut Vec<LateProfile>) -> error::Result<T>) -> Option<T> {
+        let changed = match &self.config_dir {
+            Some(dir) => profile::update_profiles_in(dir, change),
+            None => Err(error::LateError::NoHome),
+        };
+        match changed {
+            Ok((profiles, value)) => {
+                self.profiles = profiles;
+                self.profiles_changed();
+                Some(value)
+            }
+            Err(error::LateError::Name(e)) => {
+                self.profile_status = Some(e.to_string());
+                None
+            }
+            Err(e) => {
+                self.notify(format!("Could not save the profiles: {e}"));
+                None
+            }
+        }
+    }
+
+    /// brings everything showing the profiles up to date
+    fn profiles_changed(&mut self) {
+        self.profiles_names = combo_box::State::new(profile::get_profile_names(&self.profiles));
+        // unsaved edits survive other profiles being moved, duplicated or deleted
+        let drafts = std::mem::take(&mut self.profile_drafts);
+        self.profile_drafts = self.profiles.iter()
+            .map(|profile| drafts.iter()
+                .find(|draft| draft.edited && draft.saved_name == profile.name)
+                .cloned()
+                .unwrap_or_else(|| ProfileDraft::new(profile)))
+            .collect();
+        // a deleted startup profile falls back to the default one
+        if self.config.startup_profile.as_ref().is_some_and(|name| profile::choose_profile(&self.profiles, name).is_none()) {
+            self.config.startup_profile = None;
+            self.save_config();
+        }
+        // keep what was chosen, unless it is gone
+        if self.profile.as_ref().is_none_or(|name| profile::choose_profile(&self.profiles, name).is_none()) {
+            self.profile = profile::get_current_if_any(&self.profiles, self.sample_rate, self.buffer_size);
+        }
+    }
+
+    /// remembers the current values, so they can be restored if the next change isn't kept.
+    /// during a running countdown, the values from before the countdown are kept
+    fn start_revert_countdown(&mut self) {
+        if self.automatic_change || self.wizard_running() || self.config.revert_timeout_secs == 0 {
+            return;
+        }
+        let (buffer_size, sample_rate) = match self.revert.take() {
+            Some(revert) => (revert.buffer_size, revert.sample_rate),
+            None => (self.buffer_size, self.sample_rate),
+        };
+        self.revert = Some(RevertState {
+            buffer_size,
+            sample_rate,
+            remaining: self.config.revert_timeout_secs,
+        });
+    }
+
+    /// updates the displayed values after key was changed outside of this state
+    fn external_change(&mut self, key: &str, value: Option<&str>) {
+        let number = value.and_then(|v| v.parse().ok());
+        if key == FORCE_QUANTUM_KEY {
+            if self.buffer_size != number {
+                self.xruns.clear();
+            }
+            self.buffer_size = number;
+        } else if key == FORCE_RATE_KEY {
+            if self.sample_rate != number {
+                self.xruns.clear();
+            }
+            self.sample_rate = number;
+        } else if key == QUANTUM_KEY {
+            self.default_buffer_size = number;
+            return;
+        } else if key == RATE_KEY {
+            self.default_sample_rate = number;
+            return;
+        } else if key == MIN_QUANTUM_KEY {
+            self.set_min_quantum(number);
+            return;
+        } else if key == MAX_QUANTUM_KEY {
+            self.set_max_quantum(number);
+            return;
+        } else if key == ALLOWED_RATES_KEY {
+            let rates = value.map(hardware::parse_rate_list).unwrap_or_default();
+            self.allowed_rates_text = values_to_text(&rates);
+            self.set_allowed_rates(&rates);
+            return;
+        } else {
+            return;
+        }
+        self.profile = profile::get_current_if_any(&self.profiles, self.sample_rate, self.buffer_size);
+    }
+
+    /// checks for running audio clients before a change is executed.
+    /// @returns true if there are some, in which case message is kept until the user confirms it
+    fn hold_for_confirmation(&mut self, message: Message) -> bool {
+        // starting the wizard was confirmed already
+        if self.change_confirmed || self.wizard_running() {
+            return false;
+        }
+        // the clients are kept current by the pw-dump poll, running pw-dump here would block the GUI.
+        // if they can't be listed, there is nothing to warn about
+        if self.clients.is_empty() {
+            return false;
+        }
+        self.pending_change = Some(PendingChange { message, clients: self.clients.clone() });
+        true
+    }
+
+    /// @returns whether the wizard is trying buffer sizes. a finished wizard only shows its result
+    fn wizard_running(&self) -> bool {
+        self.wizard.as_ref().is_some_and(|w| !w.is_done())
+    }
+
+    /// goes back to the buffer size forced before the wizard started
+    fn restore_after_wizard(&mut self) {
+        if let Some(previous) = self.wizard.as_ref().map(|w| w.previous) {
+            // the wizard may be done already, going back must neither ask nor start a countdown
+            self.automatic_change = true;
+            self.change_confirmed = true;
+            self.update(Message::UpdateBufferSize(previous.unwrap_or(0)));
+            self.change_confirmed = false;
+            self.automatic_change = false;
+        }
+    }
+
+    fn set_persist_plan(&mut self, plan: std::io::Result<PersistPlan>) {
+        match plan {
+            Ok(plan) => {
+                self.persist_plan = Some(plan);
+                self.persist_status = None;
+            }
+            Err(e) => {
+                self.persist_plan = None;
+                self.persist_status = Some(e.to_string());
+            }
+        }
+    }
+
+    /// sets min and max quantum as a pair, see buffer_size::set_quantum_limits, and stores them.
+    /// None keeps the current value, 0 unsets it
+    fn set_quantum_limits(&mut self, min: Option<u32>, max: Option<u32>) {
+        if let Err(e) = buffer_size::set_quantum_limits(self.backend.as_ref(), min, max) {
+            self.notify(format!("Could not set the quantum limits: {e}"));
+            return;
+        }
+        if min.is_some() {
+            self.set_min_quantum(min);
+        }
+        if max.is_some() {
+            self.set_max_quantum(max);
+        }
+    }
+
+    /// stores the min quantum and updates the buffer sizes allowed by it. 0 means unset
+    fn set_min_quantum(&mut self, size: Option<u32>) {
+        self.min_quantum = size;
+        self.limits.min_quantum = size.filter(|s| *s != 0);
+        self.refresh_buffer_sizes();
+    }
+
+    /// stores the max quantum and updates the buffer sizes allowed by it. 0 means unset
+    fn set_max_quantum(&mut self, size: Option<u32>) {
+        self.max_quantum = size;
+        self.limits.max_quantum = size.filter(|s| *s != 0);
+        self.refresh_buffer_sizes();
+    }
+
+    /// stores the allowed rates and updates the sample rates offered
+    fn set_allowed_rates(&mut self, rates: &[u32]) {
+        self.limits.allowed_rates = rates.to_vec();
+        self.refresh_sample_rates();
+    }
+
+    /// offers the favourite buffer sizes the limits allow
+    fn refresh_buffer_sizes(&mut self) {
+        self.buffer_sizes = combo_box::State::new(
+            buffer_size::get_supported_buffer_sizes(&self.limits, &self.config.favourite_buffer_sizes));
+    }
+
+    /// offers the favourite and allowed sample rates the active devices support
+    fn refresh_sample_rates(&mut self) {
+        self.sample_rates = combo_box::State::new(
+            sample_rate::get_supported_sample_rates(&self.limits, &self.config.favourite_sample_rates));
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        let countdown = match self.revert {
+            Some(_) => Subscription::run(process_stream::every_second).map(|_| Message::RevertTick),
+            None => Subscription::none(),
+        };
+        let wizard_running = self.wizard_running();
+        let monitor = if self.show_monitor || wizard_running {
+            Subscription::run(pw_top::watch).map(Message::TopChanged)
+        } else {
+            Subscription::none()
+        };
+        let wizard = if wizard_running {
+            Subscription::run(process_stream::every_second).map(|_| Message::WizardTick)
+        } else {
+            Subscription::none()
+        };
+        let apps = if self.config.app_rules.is_empty() {
+            Subscription::none()
+        } else {
+            Subscription::run(app_rules::watch_apps).map(Message::AppsChanged)
+        };
+        let tray = if self.has_tray() {
+            Subscription::run_with_id("tray", tray::watch(self.tray_status.clone())).map(|event| match event {
+                Ok(command) => Message::Tray(command),
+                Err(e) => Message::Notify(e),
+            })
+        } else {
+            Subscription::none()
+        };
+        let latency = if self.show_latency {
+            Subscription::run(latency::watch).map(|devices| match devices {
+                Ok(devices) => Message::LatencyDevicesChanged(devices),
+                Err(e) => Message::Notify(e),
+            })
+        } else {
+            Subscription::none()
+        };
+        let service = if self.daemon {
+            Subscription::run(dbus_service::watch).map(Message::Notify)
+        } else {
+            Subscription::none()
+        };
+        let appearance = if self.config.follow_system_theme {
+            Subscription::run(appearance::watch).map(|event| match event {
+                Ok(scheme) => Message::ColorSchemeChanged(scheme),
+                Err(e) => Message::Notify(e),
+            })
+        } else {
+            Subscription::none()
+        };
+        Subscription::batch([
+            Subscription::run(settings_backend::watch_settings).map(|line| match line {
+                Ok(line) => Message::SettingsChanged(line),
+                Err(e) => Message::Notify(format!("Could not watch the settings: {e}")),
+            }),
+            window::close_requests().map(|_| Message::CloseRequested),
+            Subscription::run(hardware::watch_graph).map(|graph| match graph {
+                Ok(graph) => Message::GraphChanged(graph),
+                Err(e) => Message::Notify(e),
+            }),
+            countdown,
+            monitor,
+            wizard,
+            apps,
+            tray,
+            service,
+            appearance,
+            latency,
+        ])
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        if let Some(pending) = &self.pending_change {
+            return self.confirmation_view(pending);
+        }
+
         let buf_size_cbox = combo_box(
             &self.buffer_sizes,
             "Choose a buffer size",
@@ -157,34 +1440,223 @@ impl LateState {
             self.profile.as_ref(),
             Message::UpdateProfile,
         );
-        let content = column![
+        let advanced_button = button(if self.show_advanced { "Hide Advanced" } else { "Show Advanced" })
+            .on_press(Message::ToggleAdvanced);
+        let monitor_button = button(if self.show_monitor { "Hide Monitor" } else { "Show Monitor" })
+            .on_press(Message::ToggleMonitor);
+        let monitor = self.show_monitor.then(|| self.monitor_view());
+        let latency_button = button(if self.show_latency { "Hide Latency" } else { "Show Latency" })
+            .on_press(Message::ToggleLatency);
+        let latency = self.show_latency.then(|| self.latency_view());
+        let manager_button = button(if self.show_profile_manager { "Hide Profiles" } else { "Manage Profiles" })
+            .on_press(Message::ToggleProfileManager);
+        let manager = self.show_profile_manager.then(|| self.profile_manager_view());
+        let wizard = self.wizard.as_ref().map(|w| self.wizard_view(w));
+        let advanced = if self.show_advanced {
+            Some(column![
+                row![
+                    column![
+                        text("Min Quantum:"),
+                        combo_box(
+                            &self.min_quantums,
+                            "Not set",
+                            self.min_quantum.as_ref(),
+                            Message::UpdateMinQuantum,
+                        ),
+                    ],
+                    column![
+                        text("Max Quantum:"),
+                        combo_box(
+                            &self.max_quantums,
+                            "Not set",
+                            self.max_quantum.as_ref(),
+                            Message::UpdateMaxQuantum,
+                        ),
+                    ],
+                ].spacing(20),
+                column![
+                    text("Allowed Sample Rates:"),
+                    row![
+                        text_input("e.g. 44100 48000", &self.allowed_rates_text)
+                            .on_input(Message::UpdateAllowedRatesText)
+                            .on_submit(Message::ApplyAllowedRates),
+                        button("Apply").on_press(Message::ApplyAllowedRates),
+                    ].spacing(20),
+                ],
+                column![
+                    text("Favourite Buffer Sizes:"),
+                    row![
+                        text_input("e.g. 32 64 128 256", &self.favourite_buffer_sizes_text)
+                            .on_input(Message::UpdateFavouriteBufferSizesText)
+                            .on_submit(Message::ApplyFavouriteBufferSizes),
+                        button("Apply").on_press(Message::ApplyFavouriteBufferSizes),
+                    ].spacing(20),
+                ],
+                column![
+                    text("Favourite Sample Rates:"),
+                    row![
+                        text_input("e.g. 44100 48000 176400", &self.favourite_sample_rates_text)
+                            .on_input(Message::UpdateFavouriteSampleRatesText)
+                            .on_submit(Message::ApplyFavouriteSampleRates),
+                        button("Apply").on_press(Message::ApplyFavouriteSampleRates),
+                    ].spacing(20),
+                ],
+                checkbox("Save these with profiles", self.profile_with_advanced)
+                    .on_toggle(Message::ToggleProfileAdvanced),
+                checkbox("Minimize to tray when closing the window", self.config.minimize_to_tray)
+                    .on_toggle(Message::ToggleMinimizeToTray),
+                column![
+                    checkbox("Apply a profile at login", self.autostart.is_some())
+                        .on_toggle(Message::ToggleAutostart),
+                    row![
+                        pick_list(profile::get_profile_names(&self.profiles), self.config.startup_profile.clone(),
+                            |name| Message::UpdateStartupProfile(Some(name)))
+                            .placeholder("The default profile"),
+                        button("Use Default")
+                            .on_press_maybe(self.config.startup_profile.as_ref()
+                                .map(|_| Message::UpdateStartupProfile(None))),
+                    ].spacing(20),
+                ].spacing(10).push_maybe(self.autostart_status.as_ref().map(text)),
+                column![
+                    text("Revert changes after seconds (0 never reverts):"),
+                    text_input("15", &self.config.revert_timeout_secs.to_string())
+                        .on_input(Message::UpdateRevertTimeout),
+                ],
+                column![
+                    text("Seconds to try each buffer size when searching the lowest:"),
+                    text_input("30", &self.config.wizard_soak_secs.to_string())
+                        .on_input(Message::UpdateWizardSoak),
+                ],
+                column![
+                    text("Warn about these clients (comma separated):"),
+                    row![
+                        text_input("e.g. wine, yabridge", &self.fragile_clients_text)
+                            .on_input(Message::UpdateFragileClientsText)
+                            .on_submit(Message::ApplyFragileClients),
+                        button("Apply").on_press(Message::ApplyFragileClients),
+                    ].spacing(20),
+                ],
+            ].spacing(20))
+        } else {
+            None
+        };
+        let persist = match &self.persist_plan {
+            Some(plan) => {
+                let diff: Vec<String> = plan.diff().iter().map(|l| l.to_string()).collect();
+                let mut section = column![
+                    text(format!("Changes to {}:", plan.path.display())),
+                    text(diff.join("\n")).font(Font::MONOSPACE),
+                ].spacing(10);
+                for conflict in &plan.conflicts {
+                    section = section.push(text(format!("{} is also set in {}{}",
+                        conflict.key, conflict.path.display(),
+                        if conflict.overrides_late { ", which takes precedence" } else { "" })));
+                }
+                section.push(row![
+                    button("Write").on_press(Message::ConfirmPersist),
+                    button("Cancel").on_press(Message::CancelPersist),
+                ].spacing(20))
+            }
+            None => column![
+                row![
+                    button("Make Persistent")
+                        .on_press_maybe(self.profile.as_ref().map(|_| Message::PreparePersist)),
+                    button("Remove Persistent").on_press(Message::PrepareRemovePersist),
+                ].spacing(20),
+            ],
+        }.push_maybe(self.persist_status.as_ref().map(text));
+
+        let revert_banner = self.revert.as_ref().map(|revert| {
+            column![
+                text(format!("Keep these settings? Reverting in {} s", revert.remaining)),
+                row![
+                    button("Keep").on_press(Message::KeepSettings),
+                    button("Revert").on_press(Message::RevertSettings),
+                ].spacing(20),
+            ].spacing(10)
+        });
+
+        let mut notifications = column![].spacing(10);
+        for (i, message) in self.notifications.iter().enumerate() {
+            notifications = notifications.push(container(row![
+                text(message).width(iced::Length::Fill),
+                button("Dismiss").on_press(Message::DismissNotification(i)),
+            ].spacing(10).align_y(Alignment::Center))
+                .padding(10)
+                .width(iced::Length::Fill)
+                .style(|theme: &Theme| container::Style::default()
+                    .background(theme.extended_palette().danger.weak.color)
+                    .color(theme.extended_palette().danger.weak.text)));
+        }
+
+        let content = column![]
+        .push(notifications)
+        .push_maybe(revert_banner)
+        .push_maybe(wizard)
+        .push(column![
             row![
                 column![
                     text("Theme:"),
-                    pick_list(Theme::ALL, Some(&self.config.theme), Message::ThemeChanged),
-                ]
+                    if self.config.follow_system_theme {
+                        row![
+                            text("Light:"),
+                            pick_list(self.themes(), Some(&self.config.light_theme), Message::LightThemeChanged),
+                            text("Dark:"),
+                            pick_list(self.themes(), Some(&self.config.dark_theme), Message::DarkThemeChanged),
+                        ].spacing(10).align_y(Alignment::Center)
+                    } else {
+                        row![pick_list(self.themes(), Some(&self.config.theme), Message::ThemeChanged)]
+                    },
+                    row![
+                        checkbox("Follow the desktop", self.config.follow_system_theme)
+                            .on_toggle(Message::ToggleFollowSystemTheme),
+                        button("Edit Colors")
+                            .on_press_maybe(self.palette_draft.is_none().then_some(Message::EditPalette)),
+                    ].spacing(20).align_y(Alignment::Center),
+                ].spacing(10)
             ]
             .spacing(20),
+            column![].push_maybe(self.palette_draft.as_ref().map(|d| self.palette_editor_view(d))),
             row![
                 column![
                     text("Choose Profile:"),
                     row! [
                         profile_cbox,
-                        button("Delete Profile").on_press(Message::DeleteProfile),
+                        button("Delete Profile")
+                            .on_press_maybe(self.profile.as_ref().map(|_| Message::DeleteProfile)),
                     ].spacing(20)
                 ],
             ].spacing(20),
-            row![
-                column![
-                    text("Buffer Size (Latency):"),
-                    buf_size_cbox,
-                ],
-                column![
-                    text("Sample Rate"),
-                    sample_rate_cbox,
-                ],
-            ]
-            .spacing(20),
+            column![
+                row![
+                    column![
+                        text("Forced Buffer Size:"),
+                        buf_size_cbox,
+                        row![
+                            text_input("Other, e.g. 48", &self.custom_buffer_size)
+                                .on_input(Message::UpdateCustomBufferSize)
+                                .on_submit(Message::ApplyCustomBufferSize),
+                            button("Set").on_press(Message::ApplyCustomBufferSize),
+                        ].spacing(10),
+                        text(format!("Running: {} ({} ms)",
+                            value_or_unknown(self.running_buffer_size()),
+                            self.latency_as_str())),
+                    ].spacing(5),
+                    column![
+                        text("Forced Sample Rate:"),
+                        sample_rate_cbox,
+                        row![
+                            text_input("Other, e.g. 176400", &self.custom_sample_rate)
+                                .on_input(Message::UpdateCustomSampleRate)
+                                .on_submit(Message::ApplyCustomSampleRate),
+                            button("Set").on_press(Message::ApplyCustomSampleRate),
+                        ].spacing(10),
+                        text(format!("Running: {} Hz",
+                            value_or_unknown(self.running_sample_rate()))),
+                    ].spacing(5),
+                ]
+                .spacing(20),
+            ].push_maybe(self.custom_value_error.as_ref().map(text)),
             row![
                 column![
                     text("Save current profile:"),
@@ -192,25 +1664,296 @@ impl LateState {
                         profile_name_input,
                         button("Save Profile").on_press(Message::SaveProfile),
                     ].spacing(20),
-                ],
+                ].spacing(10).push_maybe(self.profile_status.as_ref().map(text)),
+            ].spacing(20),
+            manager_button,
+            column![].push_maybe(manager),
+            column![
+                text("Share profiles:"),
+                row![
+                    button("Export All").on_press(Message::ExportProfiles { all: true }),
+                    button("Export Selected")
+                        .on_press_maybe(self.profile.as_ref().map(|_| Message::ExportProfiles { all: false })),
+                ].spacing(20),
+                row![
+                    button("Import").on_press(Message::ImportProfiles),
+                    text("Existing names:"),
+                    pick_list(OnConflict::ALL, Some(self.on_conflict), Message::UpdateOnConflict),
+                ].spacing(20).align_y(Alignment::Center),
+            ].spacing(10).push_maybe(self.exchange_status.as_ref().map(text)),
+            persist,
+            row![
+                advanced_button,
+                monitor_button,
+                latency_button,
+            ].spacing(20),
+        ].spacing(20))
+        .push_maybe(monitor)
+        .push_maybe(latency)
+        .push_maybe(advanced)
+        .spacing(20)
+        .padding(20)
+        .max_width(450);
+
+        center(scrollable(content)).into()
+    }
+
+    /// load and xruns of the running drivers, as reported by pw-top
+    fn monitor_view(&self) -> Element<'_, Message> {
+        let mut drivers = column![
+            text(format!("Latency: {} ms", self.latency_as_str())),
+        ].spacing(5);
+        let running: Vec<&DriverStats> = self.top_drivers.iter().filter(|d| d.is_running()).collect();
+        if running.is_empty() {
+            drivers = drivers.push(text("No driver running"));
+        }
+        for driver in running {
+            drivers = drivers.push(text(format!("{}: {} / {} Hz, DSP {}, {} xruns",
+                driver.name,
+                value_or_unknown(driver.quantum),
+                value_or_unknown(driver.rate),
+                driver.busy.map_or("unknown".to_string(), |b| format!("{:.0} %", b * 100.0)),
+                driver.errors)));
+        }
+
+        // one bar per update, scaled to the highest count
+        let max = self.xruns.counts().iter().copied().max().unwrap_or(0).max(1);
+        let mut chart = row![].spacing(1).align_y(Alignment::End).height(40);
+        for count in self.xruns.counts() {
+            let height = 1.0 + 39.0 * *count as f32 / max as f32;
+            chart = chart.push(container(Space::new(4, height))
+                .style(|theme: &Theme| container::Style::default()
+                    .background(theme.extended_palette().danger.base.color)));
+        }
+
+        column![
+            drivers,
+            text(format!("Xruns since the last change: {}", self.xruns.sum())),
+            chart,
+            button("Find Lowest Buffer Size")
+                .on_press_maybe(self.wizard.is_none().then_some(Message::StartWizard)),
+        ].spacing(10).into()
+    }
+
+    /// what the active devices add to the latency, compared to a measured round trip
+    fn latency_view(&self) -> Element<'_, Message> {
+        let (Some(quantum), Some(rate)) = (self.running_buffer_size(), self.running_sample_rate()) else {
+            return text("Buffer size and sample rate are unknown").into();
+        };
+        let estimate = latency::estimate(&self.latency_devices, quantum, rate);
+
+        let mut breakdown = column![
+            text(format!("Graph cycle: {} frames ({:.1} ms)", quantum, estimate.graph_ms)),
+        ].spacing(5);
+        if self.latency_devices.is_empty() {
+            breakdown = breakdown.push(text("No active alsa device found"));
+        }
+        for device in &self.latency_devices {
+            let periods = match (device.period_size, device.period_num) {
+                (Some(size), Some(num)) => format!("{} × {} frames", num, size),
+                _ => "unknown".to_string(),
+            };
+            breakdown = breakdown.push(text(format!(
+                "{} ({}): {:.1} ms, headroom {} frames, periods {}, internal {} frames + {} ns",
+                device.name,
+                match device.direction { Direction::Capture => "input", Direction::Playback => "output" },
+                device.ms(quantum, rate),
+                device.headroom,
+                periods,
+                device.internal_frames,
+                device.internal_ns)));
+        }
+
+        let comparison = latency::parse_measured_ms(&self.measured_latency).map(|measured| {
+            let difference = measured - estimate.round_trip_ms();
+            text(format!("Measured {:.1} ms {} than estimated (about {} frames)",
+                difference.abs(),
+                if difference >= 0.0 { "more" } else { "less" },
+                (difference.abs() * rate as f32 / 1000.0).round()))
+        });
+
+        column![
+            breakdown,
+            text(format!("Estimated one way (playback): {:.1} ms", estimate.one_way_ms())),
+            text(format!("Estimated round trip: {:.1} ms", estimate.round_trip_ms())),
+            column![
+                text("Measured round trip in ms (e.g. with jack_iodelay):"),
+                text_input("e.g. 12.5", &self.measured_latency).on_input(Message::UpdateMeasuredLatency),
+            ].spacing(5),
+        ].spacing(10).push_maybe(comparison).into()
+    }
+
+    /// all profiles with their values, to rename, edit, reorder and mark the default
+    fn profile_manager_view(&self) -> Element<'_, Message> {
+        let last = self.profiles.len().saturating_sub(1);
+        let mut table = column![].spacing(15);
+        for (i, (profile, draft)) in self.profiles.iter().zip(&self.profile_drafts).enumerate() {
+            let edited = draft.changes(profile);
+            table = table.push(column![
+                row![
+                    text_input("Name", &draft.name)
+                        .on_input(move |name| Message::EditProfileName(i, name))
+                        .on_submit(Message::CommitProfile(i)),
+                    text_input("Buffer size", &draft.buffer_size)
+                        .on_input(move |size| Message::EditProfileBufferSize(i, size))
+                        .on_submit(Message::CommitProfile(i))
+                        .width(80),
+                    text_input("Sample rate", &draft.sample_rate)
+                        .on_input(move |rate| Message::EditProfileSampleRate(i, rate))
+                        .on_submit(Message::CommitProfile(i))
+                        .width(80),
+                ].spacing(10),
+                row![
+                    button("Save").on_press_maybe(edited.then_some(Message::CommitProfile(i))),
+                    button("↑").on_press_maybe((i > 0).then(|| Message::MoveProfile(i, i - 1))),
+                    button("↓").on_press_maybe((i < last).then_some(Message::MoveProfile(i, i + 1))),
+                    button("Duplicate").on_press(Message::DuplicateProfile(i)),
+                    button("Delete").on_press(Message::DeleteProfileAt(i)),
+                    checkbox("Default", profile.is_default)
+                        .on_toggle(move |on| Message::SetDefaultProfile(on.then_some(i))),
+                ].spacing(10).align_y(Alignment::Center),
+            ].spacing(5));
+        }
+        if self.profiles.is_empty() {
+            table = table.push(text("No profiles saved yet"));
+        }
+        table.into()
+    }
+
+    /// name and colors of a custom theme, each with a swatch of the typed color
+    fn palette_editor_view<'a>(&'a self, draft: &'a PaletteDraft) -> Element<'a, Message> {
+        let mut colors = column![
+            text_input("Theme Name", &draft.name).on_input(Message::UpdatePaletteName),
+        ].spacing(10);
+        for (i, (label, hex)) in PALETTE_COLORS.iter().zip(&draft.colors).enumerate() {
+            let swatch = iced::Color::parse(hex.trim()).unwrap_or(iced::Color::TRANSPARENT);
+            colors = colors.push(row![
+                text(*label).width(100),
+                text_input("#1e1e2e", hex).on_input(move |hex| Message::UpdatePaletteColor(i, hex)),
+                container(Space::new(24, 24))
+                    .style(move |theme: &Theme| container::Style::default()
+                        .background(swatch)
+                        .border(iced::Border::default().width(1).color(theme.palette().text))),
+            ].spacing(10).align_y(Alignment::Center));
+        }
+        let parsed = draft.parse();
+        colors
+            .push(row![
+                button("Apply").on_press_maybe(parsed.as_ref().ok().map(|_| Message::ApplyPalette)),
+                button("Cancel").on_press(Message::ClosePaletteEditor),
+            ].spacing(20))
+            .push_maybe(parsed.err().map(text))
+            .into()
+    }
+
+    /// progress and result of the buffer size wizard
+    fn wizard_view<'a>(&'a self, wizard: &StabilityWizard) -> Element<'a, Message> {
+        if !wizard.is_done() {
+            return column![
+                text(format!("Trying buffer size {}: {} s left, {} xruns",
+                    wizard.current(), wizard.remaining_secs(), wizard.xruns())),
+                button("Cancel").on_press(Message::CancelWizard),
+            ].spacing(10).into();
+        }
+
+        let Some(size) = wizard.recommendation() else {
+            return column![
+                text(format!("Even buffer size {} had xruns", wizard.current())),
+                button("Close").on_press(Message::CloseWizard),
+            ].spacing(10).into();
+        };
+        column![
+            text(format!("Buffer size {} ran without xruns", size)),
+            row![
+                button("Apply").on_press(Message::ApplyWizardResult),
+                button("Close").on_press(Message::CloseWizard),
+            ].spacing(20),
+            row![
+                text_input("Profile Name", &self.wizard_profile_name)
+                    .on_input(Message::UpdateWizardProfileName)
+                    .on_submit(Message::SaveWizardProfile),
+                button("Save Profile").on_press(Message::SaveWizardProfile),
+            ].spacing(20),
+        ].spacing(10).into()
+    }
+
+    /// asks whether to change settings while audio clients are running
+    fn confirmation_view<'a>(&self, pending: &'a PendingChange) -> Element<'a, Message> {
+        let mut clients = column![].spacing(5);
+        for client in &pending.clients {
+            let line = if client.is_fragile(&self.config.fragile_clients) {
+                format!("{} - known to crash!", client)
+            } else {
+                client.to_string()
+            };
+            clients = clients.push(text(line));
+        }
+
+        let content = column![
+            text("These programs are using audio right now. \
+                Changing buffer size or sample rate may make them crash:"),
+            clients,
+            row![
+                button("Change Anyway").on_press(Message::ConfirmChange),
+                button("Cancel").on_press(Message::CancelChange),
             ].spacing(20),
         ]
         .spacing(20)
         .padding(20)
         .max_width(450);
 
-        center(content).into()
+        center(scrollable(content)).into()
     }
 
     fn theme(&self) -> Theme {
-        self.config.theme.clone()
+        // preview the palette while editing it
+        match self.palette_draft.as_ref().map(PaletteDraft::parse) {
+            Some(Ok(custom)) => custom.to_theme(),
+            _ => appearance::theme_for(&self.config, self.color_scheme),
+        }
+    }
+
+    /// @returns the theme shown right now as stored in the config, i.e. the light or
+    /// dark one if late follows the desktop
+    fn chosen_theme_mut(&mut self) -> &mut Theme {
+        if !self.config.follow_system_theme {
+            return &mut self.config.theme;
+        }
+        match self.color_scheme {
+            ColorScheme::Dark => &mut self.config.dark_theme,
+            ColorScheme::Light | ColorScheme::NoPreference => &mut self.config.light_theme,
+        }
+    }
+
+    /// @returns the built-in themes and the custom one, if there is one
+    fn themes(&self) -> Vec<Theme> {
+        let mut themes = Theme::ALL.to_vec();
+        themes.extend(self.config.custom_theme.as_ref().map(CustomTheme::to_theme));
+        themes
     }
 }
 
 impl Default for LateState {
     fn default() -> Self {
-        let theme = config::load_config();
-        LateState::new(theme, profile::load_profiles())
+        let mut notifications = Vec::new();
+        let config = config::load_config().unwrap_or_else(|e| {
+            notifications.push(format!("Could not load the config: {e}"));
+            LateConfig { theme: Theme::Dark, ..Default::default() }
+        });
+        let profiles = profile::load_profiles().unwrap_or_else(|e| {
+            notifications.push(format!("Could not load the profiles: {e}"));
+            Vec::new()
+        });
+        // without pw-dump, all presets are offered
+        let limits = hardware::query().unwrap_or_else(|e| {
+            notifications.push(format!("Could not read what the devices support: {e}"));
+            HardwareLimits::default()
+        });
+        let mut state = LateState::new(storage::config_dir().ok(), config, profiles, Box::new(PwMetadataBackend),
+            limits);
+        state.notifications.extend(notifications);
+        state.update_tray_status();
+        state
     }
 }
 
@@ -221,12 +1964,25 @@ impl LateState{
         l.to_string()
     }
 
-    /// @returns latency in milliseconds
+    /// @returns the buffer size the graph actually runs at, as far as it is known.
+    /// the driver reports what it runs at, so it wins over a forced buffer size, which wins over clock.quantum
+    fn running_buffer_size(&self) -> Option<u32> {
+        self.driver.as_ref().and_then(|d| d.quantum)
+            .or(self.buffer_size.filter(|b| *b != 0))
+            .or(self.default_buffer_size)
+    }
+
+    /// @returns the sample rate the graph actually runs at, as far as it is known
+    fn running_sample_rate(&self) -> Option<u32> {
+        self.driver.as_ref().and_then(|d| d.rate)
+            .or(self.sample_rate.filter(|r| *r != 0))
+            .or(self.default_sample_rate)
+    }
+
+    /// @returns latency in milliseconds, computed from the running values
     fn latency(&self) -> f32 {
-        if self.buffer_size.is_some() && self.sample_rate.is_some() {
-            let buf_size = self.buffer_size.unwrap() as f32;
-            let sample_rate = self.sample_rate.unwrap() as f32;
-            buf_size * 1000.0 / sample_rate
+        if let (Some(buf_size), Some(sample_rate)) = (self.running_buffer_size(), self.running_sample_rate()) {
+            latency::frames_to_ms(buf_size, sample_rate)
         }
         else {
             0.0
@@ -234,28 +1990,619 @@ impl LateState{
     }
 }
 
+/// shows the window again after it was hidden in the tray
+fn show_window() -> Task<Message> {
+    window::get_oldest().and_then(|id| {
+        window::change_mode(id, window::Mode::Windowed).chain(window::gain_focus(id))
+    })
+}
+
+/// @returns the values as shown in the allowed rates and favourites inputs, e.g. "44100 48000"
+fn values_to_text(values: &[u32]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    values.join(" ")
+}
+
+/// parses the allowed rates or favourites input. anything that isn't a number is ignored
+fn text_to_values(text: &str) -> Vec<u32> {
+    hardware::parse_rate_list(text)
+}
+
+fn value_or_unknown(value: Option<u32>) -> String {
+    value.map_or("?".to_string(), |v| v.to_string())
+}
 
 fn main() -> iced::Result {
+    // any argument but --daemon means headless mode, see cli.rs
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(dir) = cli::take_config_dir(&mut args) {
+        storage::set_config_dir(dir);
+    }
+    let daemon = args == ["--daemon"];
+    if !args.is_empty() && !daemon {
+        std::process::exit(cli::run(&args, &PwMetadataBackend));
+    }
+
     let icon = iced::window::icon::from_file("resources/late.ico");
     let ico_opt: Option<iced::window::Icon> = icon.ok();
     let win_settings = iced::window::Settings {
-        size: iced::Size::new(480.0, 330.0),
+        size: iced::Size::new(480.0, 380.0),
         position: iced::window::Position::Default,
         min_size: None,
         max_size: None,
-        visible: true,
+        // the daemon starts in the tray
+        visible: !daemon,
         resizable: true,
         decorations: true,
         transparent: false,
         level: iced::window::Level::Normal,
         icon: ico_opt,
         platform_specific: iced::window::settings::PlatformSpecific { application_id: "Late".to_owned(), override_redirect: false },
-        exit_on_close_request: true,
+        // closing may only hide the window, see LateState::handle
+        exit_on_close_request: false,
     };
 
-    iced::application("Late - Pipewire Preferences", LateState::update, LateState::view)
+    iced::application("Late - Pipewire Preferences", LateState::handle, LateState::view)
         .theme(LateState::theme)
+        .subscription(LateState::subscription)
         .window(win_settings)
-        .run()
+        .run_with(move || (LateState { daemon, window_hidden: daemon, ..LateState::default() }, Task::none()))
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use settings_backend::MemoryBackend;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn recording_profile() -> LateProfile {
+        LateProfile {
+            name: "Recording".to_string(),
+            buffer_size: 64,
+            sample_rate: 48000,
+            ..Default::default()
+        }
+    }
+
+    fn state_with(backend: &MemoryBackend) -> LateState {
+        LateState::new(Some(test_config_dir()), LateConfig::default(), vec![recording_profile()],
+            Box::new(backend.clone()), HardwareLimits::default())
+    }
+
+    /// a config dir of its own for each test, so that tests don't see what others saved,
+    /// and nothing is saved in the real config dir
+    fn test_config_dir() -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("late-test-{}-{}", std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)));
+        // left over by an earlier run with the same pid
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// a backend as if pipewire wasn't running
+    struct UnreachableBackend;
+
+    impl SettingsBackend for UnreachableBackend {
+        fn get(&self, _key: &str) -> error::Result<Option<String>> {
+            Err(error::LateError::Exit {
+                program: "pw-metadata".to_string(),
+                code: Some(255),
+                stderr: "can't connect: Host is down".to_string(),
+            })
+        }
+
+        fn set(&self, key: &str, _value: &str) -> error::Result<()> {
+            self.get(key).map(|_| ())
+        }
+
+        fn reset(&self, key: &str) -> error::Result<()> {
+            self.get(key).map(|_| ())
+        }
+
+        fn active_clients(&self) -> error::Result<Vec<AudioClient>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn errors_are_shown_until_dismissed() {
+        let mut state = LateState::new(Some(test_config_dir()), LateConfig::default(), Vec::new(),
+            Box::new(UnreachableBackend), HardwareLimits::default());
+        assert_eq!(state.notifications.len(), 3);
+        assert_eq!(state.notifications[2], "Could not read the defaults and limits of pipewire: \
+            pw-metadata exited with code 255: can't connect: Host is down");
+
+        state.update(Message::UpdateBufferSize(128));
+        assert_eq!(state.notifications.last().unwrap(),
+            "Could not set the buffer size: pw-metadata exited with code 255: can't connect: Host is down");
+        // the same error again isn't repeated
+        state.update(Message::UpdateBufferSize(128));
+        assert_eq!(state.notifications.len(), 4);
+
+        state.update(Message::DismissNotification(0));
+        assert_eq!(state.notifications.len(), 3);
+    }
+
+    #[test]
+    fn new_reads_current_settings_and_matches_profile() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "64").unwrap();
+        backend.set(FORCE_RATE_KEY, "48000").unwrap();
+
+        let state = state_with(&backend);
+        assert_eq!(state.buffer_size, Some(64));
+        assert_eq!(state.sample_rate, Some(48000));
+        assert_eq!(state.profile.as_deref(), Some("Recording"));
+    }
+
+    #[test]
+    fn update_buffer_size_writes_force_quantum() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+
+        state.update(Message::UpdateBufferSize(256));
+        assert_eq!(state.buffer_size, Some(256));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+
+        // 0 acts as a reset
+        state.update(Message::UpdateBufferSize(0));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+    }
+
+    #[test]
+    fn typed_values_are_checked_against_limits() {
+        let backend = MemoryBackend::default();
+        let limits = HardwareLimits { min_quantum: Some(32), ..Default::default() };
+        let mut state = LateState::new(Some(test_config_dir()), LateConfig::default(), Vec::new(),
+            Box::new(backend.clone()), limits);
+
+        state.update(Message::UpdateCustomBufferSize("16".to_string()));
+        state.update(Message::ApplyCustomBufferSize);
+        assert_eq!(state.custom_value_error.as_deref(),
+            Some("Buffer size 16: outside of the quantum limits (min 32, max not set)"));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+
+        state.update(Message::UpdateCustomBufferSize("48".to_string()));
+        assert_eq!(state.custom_value_error, None);
+        state.update(Message::ApplyCustomBufferSize);
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("48"));
+        assert_eq!(state.custom_buffer_size, "");
+
+        state.update(Message::UpdateCustomSampleRate("176400".to_string()));
+        state.update(Message::ApplyCustomSampleRate);
+        assert_eq!(state.sample_rate, Some(176400));
+    }
+
+    #[test]
+    fn update_profile_applies_buffer_size_and_sample_rate() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+
+        state.update(Message::UpdateProfile("Recording".to_string()));
+        assert_eq!(state.profile.as_deref(), Some("Recording"));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap().as_deref(), Some("48000"));
+        assert_eq!(state.latency(), 64.0 * 1000.0 / 48000.0);
+    }
+
+    #[test]
+    fn running_values_fall_back_to_driver_and_defaults() {
+        let backend = MemoryBackend::default();
+        backend.set(QUANTUM_KEY, "1024").unwrap();
+        backend.set(RATE_KEY, "48000").unwrap();
+        let mut state = state_with(&backend);
+        assert_eq!(state.running_buffer_size(), Some(1024));
+        assert_eq!(state.running_sample_rate(), Some(48000));
+
+        state.update(Message::UpdateBufferSize(128));
+        assert_eq!(state.running_buffer_size(), Some(128));
+
+        // e.g. a client asked for a larger quantum than the forced one
+        let driver = |quantum, rate| Message::GraphChanged(GraphState {
+            driver: Some(DriverClock { name: "alsa_output".to_string(), quantum, rate }),
+            ..Default::default()
+        });
+        state.update(driver(Some(512), Some(44100)));
+        assert_eq!(state.running_buffer_size(), Some(512));
+        assert_eq!(state.running_sample_rate(), Some(44100));
+        assert_eq!(state.latency(), 512.0 * 1000.0 / 44100.0);
+
+        // a driver which doesn't say what it runs at
+        state.update(driver(None, None));
+        assert_eq!(state.running_buffer_size(), Some(128));
+        assert_eq!(state.running_sample_rate(), Some(48000));
+    }
+
+    #[test]
+    fn profile_applies_advanced_values() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+        state.profiles.push(LateProfile {
+            name: "Mixing".to_string(),
+            buffer_size: 0,
+            sample_rate: 0,
+            min_quantum: Some(256),
+            max_quantum: Some(2048),
+            allowed_rates: Some(vec![44100, 48000]),
+            ..Default::default()
+        });
+
+        state.update(Message::UpdateProfile("Mixing".to_string()));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("2048"));
+        assert_eq!(backend.get(ALLOWED_RATES_KEY).unwrap().as_deref(), Some("[ 44100 48000 ]"));
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap(), None);
+        assert_eq!(state.allowed_rates_text, "44100 48000");
+        // 64 and 128 are below the new min quantum
+        assert_eq!(state.limits.min_quantum, Some(256));
+        assert_eq!(state.buffer_sizes.options(), &[0, 256, 512, 1024, 2048]);
+    }
+
+    /// a backend which refuses a min quantum above the max quantum, to see the order limits are written in
+    #[derive(Clone, Default)]
+    struct StrictBackend(MemoryBackend);
+
+    impl SettingsBackend for StrictBackend {
+        fn get(&self, key: &str) -> error::Result<Option<String>> {
+            self.0.get(key)
+        }
+
+        fn set(&self, key: &str, value: &str) -> error::Result<()> {
+            let limit = |name: &str| if name == key { value.parse().ok() } else {
+                self.0.get(name).unwrap().and_then(|v| v.parse().ok())
+            };
+            buffer_size::check_quantum_limits(limit(MIN_QUANTUM_KEY), limit(MAX_QUANTUM_KEY))?;
+            self.0.set(key, value)
+        }
+
+        fn reset(&self, key: &str) -> error::Result<()> {
+            self.0.reset(key)
+        }
+
+        fn active_clients(&self) -> error::Result<Vec<AudioClient>> {
+            self.0.active_clients()
+        }
+    }
+
+    #[test]
+    fn profile_raises_and_lowers_both_limits() {
+        let backend = StrictBackend::default();
+        backend.set(MIN_QUANTUM_KEY, "64").unwrap();
+        backend.set(MAX_QUANTUM_KEY, "1024").unwrap();
+        let mut state = LateState::new(Some(test_config_dir()), LateConfig::default(), Vec::new(),
+            Box::new(backend.clone()), HardwareLimits::default());
+        let limits = |name: &str, min, max| LateProfile {
+            name: name.to_string(),
+            min_quantum: Some(min),
+            max_quantum: Some(max),
+            ..Default::default()
+        };
+        state.profiles = vec![limits("Big", 2048, 8192), limits("Small", 32, 256)];
+
+        state.update(Message::UpdateProfile("Big".to_string()));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("2048"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("8192"));
+        assert_eq!((state.min_quantum, state.max_quantum), (Some(2048), Some(8192)));
+
+        state.update(Message::UpdateProfile("Small".to_string()));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("32"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+        assert!(state.notifications.is_empty(), "{:?}", state.notifications);
+
+        // a profile whose min is above its max is refused as a whole
+        state.profiles.push(limits("Broken", 512, 128));
+        state.update(Message::UpdateProfile("Broken".to_string()));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("32"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+    }
+
+    #[test]
+    fn min_quantum_must_not_exceed_max_quantum() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+        state.update(Message::UpdateMaxQuantum(256));
+
+        state.update(Message::UpdateMinQuantum(512));
+        assert_eq!(state.notifications.last().unwrap(),
+            "Could not set the quantum limits: the min quantum 512 is larger than the max quantum 256");
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap(), None);
+        assert_eq!(state.min_quantum, None);
+
+        state.update(Message::UpdateMinQuantum(128));
+        state.update(Message::UpdateMaxQuantum(64));
+        assert_eq!(state.notifications.last().unwrap(),
+            "Could not set the quantum limits: the min quantum 128 is larger than the max quantum 64");
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+
+        // 0 unsets it
+        state.update(Message::UpdateMaxQuantum(0));
+        assert_eq!(state.limits.max_quantum, None);
+    }
+
+    #[test]
+    fn running_clients_need_confirmation() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+        state.update(Message::GraphChanged(GraphState {
+            clients: vec![AudioClient {
+                name: "Amped Roots".to_string(),
+                binary: Some("wine64-preloader".to_string()),
+                pid: Some(1234),
+                media_class: "Stream/Output/Audio".to_string(),
+            }],
+            ..Default::default()
+        }));
+
+        state.update(Message::UpdateProfile("Recording".to_string()));
+        assert!(state.pending_change.is_some());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+
+        state.update(Message::CancelChange);
+        assert!(state.pending_change.is_none());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+
+        state.update(Message::UpdateProfile("Recording".to_string()));
+        state.update(Message::ConfirmChange);
+        assert!(state.pending_change.is_none());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap().as_deref(), Some("48000"));
+        assert_eq!(state.profile.as_deref(), Some("Recording"));
+    }
+
+    #[test]
+    fn changes_revert_unless_kept() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "1024").unwrap();
+        let mut state = state_with(&backend);
+        state.config.revert_timeout_secs = 2;
+
+        state.update(Message::UpdateProfile("Recording".to_string()));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        state.update(Message::RevertTick);
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        state.update(Message::RevertTick);
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("1024"));
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap(), None);
+        assert!(state.revert.is_none());
+        assert_eq!(state.profile, None);
+
+        state.update(Message::UpdateBufferSize(256));
+        state.update(Message::KeepSettings);
+        state.update(Message::RevertTick);
+        state.update(Message::RevertTick);
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+    }
+
+    #[test]
+    fn wizard_restores_buffer_size_and_recommends_clean_size() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "256").unwrap();
+        let mut state = state_with(&backend);
+        state.config.wizard_soak_secs = 3;
+        let top = |errors| vec![DriverStats { state: 'R', errors, ..Default::default() }];
+
+        state.update(Message::StartWizard);
+        state.update(Message::TopChanged(top(0)));
+        for _ in 0..3 {
+            state.update(Message::WizardTick);
+        }
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("128"));
+        for _ in 0..2 {
+            state.update(Message::WizardTick);
+        }
+        state.update(Message::TopChanged(top(2)));
+        state.update(Message::WizardTick);
+
+        assert!(state.revert.is_none());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+        assert_eq!(state.wizard.as_ref().and_then(|w| w.recommendation()), Some(256));
+
+        state.update(Message::ApplyWizardResult);
+        assert!(state.wizard.is_none());
+        assert!(state.revert.is_some());
+    }
+
+    #[test]
+    fn finished_wizard_no_longer_skips_countdown_and_confirmation() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "1024").unwrap();
+        let mut state = state_with(&backend);
+        state.config.wizard_soak_secs = 3;
+
+        // a change still to be kept is undone before the wizard starts
+        state.update(Message::UpdateBufferSize(128));
+        assert!(state.revert.is_some());
+        state.update(Message::StartWizard);
+        assert!(state.revert.is_none());
+        assert_eq!(state.wizard.as_ref().unwrap().previous, Some(1024));
+
+        let top = |errors| Message::TopChanged(vec![DriverStats { state: 'R', errors, ..Default::default() }]);
+        state.update(Message::WizardTick);
+        state.update(Message::WizardTick);
+        state.update(top(0));
+        state.update(top(1));
+        state.update(Message::WizardTick);
+        assert!(state.wizard.as_ref().unwrap().is_done());
+        assert!(state.revert.is_none());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("1024"));
+
+        state.update(Message::UpdateBufferSize(256));
+        assert!(state.revert.is_some());
+        state.update(Message::GraphChanged(GraphState {
+            clients: vec![AudioClient {
+                name: "REAPER".to_string(),
+                binary: Some("reaper".to_string()),
+                pid: Some(4321),
+                media_class: "Stream/Input/Audio".to_string(),
+            }],
+            ..Default::default()
+        }));
+        state.update(Message::UpdateBufferSize(512));
+        assert!(state.pending_change.is_some());
+    }
+
+    #[test]
+    fn tray_changes_with_hidden_window_are_kept() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "1024").unwrap();
+        let mut state = state_with(&backend);
+        state.daemon = true;
+
+        let _ = state.handle(Message::CloseRequested);
+        let _ = state.handle(Message::Tray(TrayCommand::ApplyProfile("Recording".to_string())));
+        assert!(state.revert.is_none());
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        // the tray shows what the window would
+        assert_eq!(*state.tray_status.lock().unwrap(), TrayStatus {
+            quantum: Some(64),
+            rate: Some(48000),
+            profiles: vec!["Recording".to_string()],
+            profile: Some("Recording".to_string()),
+        });
+
+        // with the window open, the change can be kept there
+        let _ = state.handle(Message::Tray(TrayCommand::ShowWindow));
+        state.update(Message::UpdateBufferSize(1024));
+        state.update(Message::KeepSettings);
+        let _ = state.handle(Message::Tray(TrayCommand::ApplyProfile("Recording".to_string())));
+        assert!(state.revert.is_some());
+    }
+
+    #[test]
+    fn renaming_a_profile_updates_rules_and_keeps_other_drafts() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+        state.profiles.push(LateProfile { name: "Mixing".to_string(), buffer_size: 1024, ..Default::default() });
+        state.profiles.push(LateProfile { name: "Live".to_string(), buffer_size: 128, ..Default::default() });
+        let dir = state.config_dir.clone().unwrap();
+        profile::update_profiles_in(&dir, |profiles| {
+            *profiles = state.profiles.clone();
+            Ok(())
+        }).unwrap();
+        state.config.app_rules = vec![app_rules::AppRule {
+            matches: vec!["reaper".to_string()],
+            profile: "Recording".to_string(),
+        }];
+        state.update(Message::ToggleProfileManager);
+
+        state.update(Message::EditProfileBufferSize(2, "256".to_string()));
+        state.update(Message::EditProfileName(0, "Tracking".to_string()));
+        state.update(Message::CommitProfile(0));
+        assert_eq!(state.config.app_rules[0].profile, "Tracking");
+        assert_eq!(state.profile_drafts[2].buffer_size, "256");
+
+        state.update(Message::MoveProfile(2, 0));
+        state.update(Message::DuplicateProfile(1));
+        state.update(Message::DeleteProfileAt(2));
+        let drafts: Vec<(&str, &str)> = state.profile_drafts.iter()
+            .map(|d| (d.name.as_str(), d.buffer_size.as_str()))
+            .collect();
+        assert_eq!(drafts, vec![("Live", "256"), ("Tracking", "64"), ("Mixing", "1024")]);
+        assert!(state.profile_drafts[0].changes(&state.profiles[0]));
+
+        // rows which are gone already are ignored
+        state.update(Message::DeleteProfileAt(3));
+        state.update(Message::MoveProfile(3, 0));
+        state.update(Message::DuplicateProfile(3));
+        state.update(Message::SetDefaultProfile(Some(3)));
+        assert_eq!(profile::get_profile_names(&state.profiles), vec!["Live", "Tracking", "Mixing"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn app_rules_apply_profile_and_restore() {
+        let backend = MemoryBackend::default();
+        backend.set(FORCE_QUANTUM_KEY, "1024").unwrap();
+        let mut state = state_with(&backend);
+        let dir = state.config_dir.clone().unwrap();
+        profile::update_profiles_in(&dir, |profiles| {
+            *profiles = state.profiles.clone();
+            Ok(())
+        }).unwrap();
+        state.config.app_rules = vec![app_rules::AppRule {
+            matches: vec!["reaper".to_string()],
+            profile: "Recording".to_string(),
+        }];
+        let reaper = App { name: Some("REAPER".to_string()), binary: Some("reaper".to_string()) };
+
+        state.update(Message::AppsChanged(vec![reaper]));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        assert_eq!(state.profile.as_deref(), Some("Recording"));
+        assert!(state.revert.is_none());
+
+        state.update(Message::AppsChanged(vec![]));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("1024"));
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap(), None);
+        assert_eq!(state.profile, None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn app_rules_restore_the_limits_of_their_profile() {
+        let backend = MemoryBackend::default();
+        backend.set(MIN_QUANTUM_KEY, "32").unwrap();
+        backend.set(MAX_QUANTUM_KEY, "2048").unwrap();
+        let mut state = state_with(&backend);
+        state.profiles.push(LateProfile {
+            name: "Tight".to_string(),
+            buffer_size: 256,
+            sample_rate: 48000,
+            min_quantum: Some(256),
+            max_quantum: Some(512),
+            allowed_rates: Some(vec![48000]),
+            ..Default::default()
+        });
+        state.config.app_rules = vec![
+            app_rules::AppRule { matches: vec!["reaper".to_string()], profile: "Tight".to_string() },
+            app_rules::AppRule { matches: vec!["ardour".to_string()], profile: "Recording".to_string() },
+        ];
+        let app = |binary: &str| App { name: None, binary: Some(binary.to_string()) };
+
+        state.update(Message::AppsChanged(vec![app("reaper")]));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("512"));
+        assert_eq!(backend.get(ALLOWED_RATES_KEY).unwrap().as_deref(), Some("[ 48000 ]"));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("256"));
+
+        // switching to a rule whose profile leaves the limits alone still restores them in the end
+        state.update(Message::AppsChanged(vec![app("ardour")]));
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap().as_deref(), Some("64"));
+        state.update(Message::AppsChanged(vec![]));
+        assert_eq!(backend.get(MIN_QUANTUM_KEY).unwrap().as_deref(), Some("32"));
+        assert_eq!(backend.get(MAX_QUANTUM_KEY).unwrap().as_deref(), Some("2048"));
+        assert_eq!(backend.get(ALLOWED_RATES_KEY).unwrap(), None);
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+        assert_eq!(backend.get(FORCE_RATE_KEY).unwrap(), None);
+        assert_eq!(state.limits.min_quantum, Some(32));
+        assert!(state.notifications.is_empty(), "{:?}", state.notifications);
+    }
+
+    #[test]
+    fn external_changes_update_values_and_profile() {
+        let backend = MemoryBackend::default();
+        let mut state = state_with(&backend);
+        let update = |key: &str, value: &str| Message::SettingsChanged(MetadataLine::Update(
+            pw_metadata::MetadataEntry {
+                id: 0,
+                key: key.to_string(),
+                value: value.to_string(),
+                value_type: String::new(),
+            }));
+
+        state.update(update(FORCE_RATE_KEY, "48000"));
+        state.update(update(FORCE_QUANTUM_KEY, "64"));
+        assert_eq!(state.buffer_size, Some(64));
+        assert_eq!(state.profile.as_deref(), Some("Recording"));
+
+        state.update(Message::SettingsChanged(MetadataLine::Remove {
+            id: 0,
+            key: Some(FORCE_QUANTUM_KEY.to_string()),
+        }));
+        assert_eq!(state.buffer_size, None);
+        assert_eq!(state.profile, None);
+        // nothing was written
+        assert_eq!(backend.get(FORCE_QUANTUM_KEY).unwrap(), None);
+    }
+}
@@ -1,5 +0,0 @@
-
-
-pub static CONFIG_PATH: &str = ".config/late";
-pub static CONFIG_NAME: &str = "late_config.json";
-pub static PROFILES_NAME: &str = "late_profiles.json";
@@ -0,0 +1,302 @@
+
+// makes a profile survive pipewire restarts.
+// values set through pw-metadata are lost when pipewire restarts. to keep them,
+// late writes a drop-in file for pipewire, which sets the default clock values:
+// ~/.config/pipewire/pipewire.conf.d/late.conf
+// the file is only ever touched if late created it (see MANAGED_HEADER).
+// pipewire reads the drop-ins of the system and the user dirs together, sorted by file name,
+// so drop-ins of the distribution or the admin can override late's as well.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::profile::LateProfile;
+use crate::sample_rate;
+use crate::storage;
+
+/// first line of the drop-in. files without it were not written by late and are left alone
+pub static MANAGED_HEADER: &str = "# managed by late - changes will be overwritten";
+static DROP_IN_DIR: &str = "pipewire/pipewire.conf.d";
+static DROP_IN_NAME: &str = "late.conf";
+/// the drop-in dirs of the distribution and the admin
+static SYSTEM_DROP_IN_DIRS: [&str; 2] = [
+    "/usr/share/pipewire/pipewire.conf.d",
+    "/etc/pipewire/pipewire.conf.d",
+];
+
+/// the context.properties keys late writes
+static KEYS: [&str; 5] = [
+    "default.clock.rate",
+    "default.clock.quantum",
+    "default.clock.min-quantum",
+    "default.clock.max-quantum",
+    "default.clock.allowed-rates",
+];
+
+/// One line of a diff between the current and the new drop-in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+impl std::fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffLine::Same(l) => write!(f, "  {}", l),
+            DiffLine::Added(l) => write!(f, "+ {}", l),
+            DiffLine::Removed(l) => write!(f, "- {}", l),
+        }
+    }
+}
+
+/// Another drop-in setting one of the keys late writes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub key: String,
+    /// pipewire reads drop-ins in alphabetical order, the last one wins
+    pub overrides_late: bool,
+}
+
+/// What making a profile persistent (or undoing it) would change
+#[derive(Debug, Clone)]
+pub struct PersistPlan {
+    pub path: PathBuf,
+    /// the content of the drop-in now, empty if there is none
+    pub old: String,
+    /// the content after applying the plan. None removes the drop-in
+    pub new: Option<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl PersistPlan {
+    pub fn diff(&self) -> Vec<DiffLine> {
+        diff(&self.old, self.new.as_deref().unwrap_or_default())
+    }
+
+    /// writes or removes the drop-in
+    pub fn apply(&self) -> io::Result<()> {
+        match &self.new {
+            Some(content) => {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                // pipewire must never read a half written drop-in
+                storage::replace_file(&self.path, content)
+            }
+            None if self.path.exists() => fs::remove_file(&self.path),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn drop_in_dir() -> io::Result<PathBuf> {
+    // pipewire looks in $XDG_CONFIG_HOME, too
+    let mut dir = storage::xdg_config_home().map_err(io::Error::other)?;
+    dir.push(DROP_IN_DIR);
+    Ok(dir)
+}
+
+/// @returns the plan to make profile persistent.
+/// a profile which sets no values is refused, the drop-in would change nothing
+pub fn plan_write(profile: &LateProfile) -> io::Result<PersistPlan> {
+    if properties(profile).is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "profile '{}' leaves every value to pipewire, there is nothing to persist", profile.name)));
+    }
+    plan(Some(render(profile)))
+}
+
+/// @returns the plan to remove the drop-in again
+pub fn plan_remove() -> io::Result<PersistPlan> {
+    plan(None)
+}
+
+fn plan(new: Option<String>) -> io::Result<PersistPlan> {
+    let dir = drop_in_dir()?;
+    let path = dir.join(DROP_IN_NAME);
+    let old = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if !old.is_empty() && !old.starts_with(MANAGED_HEADER) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!(
+            "{} was not written by late, move it away to let late manage it", path.display())));
+    }
+
+    let mut dirs: Vec<PathBuf> = SYSTEM_DROP_IN_DIRS.iter().map(PathBuf::from).collect();
+    dirs.push(dir);
+    Ok(PersistPlan {
+        conflicts: find_conflicts(&dirs),
+        path,
+        old,
+        new,
+    })
+}
+
+/// renders the drop-in for profile. values of 0 are left to the pipewire defaults
+pub fn render(profile: &LateProfile) -> String {
+    // the name is a comment, a line break would end it
+    let name = profile.name.replace(|c: char| c.is_control(), " ");
+    format!("{}\n# profile: {}\ncontext.properties = {{\n{}\n}}\n",
+        MANAGED_HEADER, name, properties(profile).join("\n"))
+}
+
+/// @returns the lines of context.properties for the values profile sets
+fn properties(profile: &LateProfile) -> Vec<String> {
+    let mut properties = Vec::new();
+    let mut push = |key: &str, value: Option<String>| {
+        if let Some(value) = value {
+            properties.push(format!("    {:<27} = {}", key, value));
+        }
+    };
+    let non_zero = |v: u32| Some(v).filter(|v| *v != 0).map(|v| v.to_string());
+
+    push(KEYS[0], non_zero(profile.sample_rate));
+    push(KEYS[1], non_zero(profile.buffer_size));
+    push(KEYS[2], profile.min_quantum.and_then(non_zero));
+    push(KEYS[3], profile.max_quantum.and_then(non_zero));
+    push(KEYS[4], profile.allowed_rates.as_ref()
+        .filter(|r| !r.is_empty())
+        .map(|r| sample_rate::format_rate_list(r)));
+    properties
+}
+
+/// @returns the keys late writes, which other drop-ins in dirs set as well
+fn find_conflicts(dirs: &[PathBuf]) -> Vec<Conflict> {
+    let mut files: Vec<PathBuf> = dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "conf"))
+        .filter(|p| p.file_name().is_some_and(|n| n != DROP_IN_NAME))
+        .collect();
+    // the order pipewire reads them in
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+
+    let mut conflicts = Vec::new();
+    for path in files {
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let overrides_late = path.file_name()
+            .is_some_and(|n| n.to_string_lossy().as_ref() > DROP_IN_NAME);
+        for key in KEYS {
+            let is_set = content.lines()
+                .map(|l| l.split('#').next().unwrap_or_default())
+                .any(|l| l.split(|c: char| c.is_whitespace() || c == '=' || c == ':')
+                    .any(|word| word == key));
+            if is_set {
+                conflicts.push(Conflict { path: path.clone(), key: key.to_string(), overrides_late });
+            }
+        }
+    }
+    conflicts
+}
+
+/// line based diff between old and new, using the longest common subsequence
+pub fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] is the length of the common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_only_set_values() {
+        let profile = LateProfile {
+            name: "Recording".to_string(),
+            buffer_size: 64,
+            sample_rate: 48000,
+            max_quantum: Some(0),
+            allowed_rates: Some(vec![44100, 48000]),
+            ..Default::default()
+        };
+        assert_eq!(render(&profile), format!("{}\n{}", MANAGED_HEADER, "\
+# profile: Recording
+context.properties = {
+    default.clock.rate          = 48000
+    default.clock.quantum       = 64
+    default.clock.allowed-rates = [ 44100 48000 ]
+}
+"));
+    }
+
+    #[test]
+    fn refuses_profiles_without_values() {
+        let profile = LateProfile { name: "Defaults".to_string(), max_quantum: Some(0), ..Default::default() };
+        assert_eq!(plan_write(&profile).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let profile = LateProfile { name: "Two\nlines".to_string(), buffer_size: 64, ..Default::default() };
+        assert!(render(&profile).contains("\n# profile: Two lines\n"));
+    }
+
+    #[test]
+    fn finds_conflicts_in_system_and_user_dirs() {
+        let dir = std::env::temp_dir().join(format!("late-persist-{}", std::process::id()));
+        let (system, user) = (dir.join("etc"), dir.join("user"));
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(&user).unwrap();
+        fs::write(system.join("10-rates.conf"), "context.properties = {\n    default.clock.rate = 44100\n}\n").unwrap();
+        fs::write(user.join("lowlatency.conf"),
+            "# default.clock.rate = 96000\ncontext.properties = { default.clock.quantum = 32 }\n").unwrap();
+        fs::write(user.join(DROP_IN_NAME), MANAGED_HEADER).unwrap();
+
+        assert_eq!(find_conflicts(&[system.clone(), user.clone()]), vec![
+            Conflict { path: system.join("10-rates.conf"), key: KEYS[0].to_string(), overrides_late: false },
+            Conflict { path: user.join("lowlatency.conf"), key: KEYS[1].to_string(), overrides_late: true },
+        ]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diffs_changed_lines() {
+        let old = "a\nb\nc\n";
+        let new = "a\nB\nc\nd\n";
+        assert_eq!(diff(old, new), vec![
+            DiffLine::Same("a".to_string()),
+            DiffLine::Removed("b".to_string()),
+            DiffLine::Added("B".to_string()),
+            DiffLine::Same("c".to_string()),
+            DiffLine::Added("d".to_string()),
+        ]);
+        assert_eq!(diff("", "x"), vec![DiffLine::Added("x".to_string())]);
+        assert_eq!(diff("x", ""), vec![DiffLine::Removed("x".to_string())]);
+    }
+}
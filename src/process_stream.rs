@@ -0,0 +1,198 @@
+
+// turns the stdout of a long running process (e.g. pw-metadata -m) or repeated
+// blocking calls (e.g. pw-dump) into a stream, so it can be fed into an iced Subscription.
+// reading happens on a separate thread, since the default iced executor is a
+// small thread pool which must not be blocked.
+// a long running process which exits (e.g. because pipewire restarted) is started again,
+// waiting a bit longer each time it exits without printing anything.
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use iced::futures::channel::mpsc;
+use iced::futures::{SinkExt, Stream, StreamExt};
+
+use crate::error::LateError;
+
+/// the pause before the first restart of a process
+const FIRST_RESTART_DELAY: Duration = Duration::from_secs(1);
+/// the longest pause between restarts
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// how much of stderr is kept to report why a process stopped
+const MAX_STDERR: u64 = 4096;
+
+/// What a long running process printed, or why it stopped
+#[derive(Debug)]
+pub enum Output {
+    /// a line of stdout. nr counts from 1 for every start of the process
+    Line { nr: usize, line: String },
+    /// the process could not be started or exited. it is started again after a pause,
+    /// so anything derived from the previous lines is out of date
+    Stopped(LateError),
+}
+
+/// spawns program with args and streams its stdout line by line.
+/// the process is started again whenever it exits or can't be started.
+/// once the stream is dropped, the process is killed with the next line it prints
+pub fn lines(program: &'static str, args: &'static [&'static str]) -> impl Stream<Item = Output> {
+    iced::stream::channel(100, move |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            let mut delay = FIRST_RESTART_DELAY;
+            loop {
+                let (printed, error) = read_lines(program, args, &sender);
+                if sender.unbounded_send(Output::Stopped(error)).is_err() {
+                    break;
+                }
+                // a process which ran for a while gets restarted quickly
+                delay = if printed { FIRST_RESTART_DELAY } else { (delay * 2).min(MAX_RESTART_DELAY) };
+                thread::sleep(delay);
+                if sender.is_closed() {
+                    break;
+                }
+            }
+        });
+
+        while let Some(line) = receiver.next().await {
+            if output.send(line).await.is_err() {
+                // the subscription was dropped
+                break;
+            }
+        }
+    })
+}
+
+/// calls f every interval on a separate thread and streams its results, until nobody listens anymore.
+/// a result is only sent if it differs from the previous one
+pub fn poll<T>(interval: Duration, f: fn() -> T) -> impl Stream<Item = T>
+where
+    T: PartialEq + Clone + Send + 'static,
+{
+    iced::stream::channel(10, move |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            let mut previous = None;
+            // an unchanged result isn't sent, so sending can't tell that nobody listens
+            while !sender.is_closed() {
+                let current = f();
+                if previous.as_ref() != Some(&current) {
+                    if sender.unbounded_send(current.clone()).is_err() {
+                        break;
+                    }
+                    previous = Some(current);
+                }
+                thread::sleep(interval);
+            }
+        });
+
+        while let Some(value) = receiver.next().await {
+            if output.send(value).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// @returns a stream yielding once every second, e.g. for countdowns
+pub fn every_second() -> impl Stream<Item = ()> {
+    iced::stream::channel(1, |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                if sender.unbounded_send(()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Some(tick) = receiver.next().await {
+            if output.send(tick).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// runs program until it exits or nobody listens anymore
+/// @returns whether it printed anything and why it stopped
+fn read_lines(program: &str, args: &[&str], sender: &mpsc::UnboundedSender<Output>) -> (bool, LateError) {
+    let child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(source) => return (false, LateError::Spawn { program: program.to_string(), source }),
+    };
+    // read alongside stdout, so that the process never blocks on a full stderr pipe
+    let stderr = child.stderr.take().map(|stderr| thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut start = Vec::new();
+        let _ = (&mut reader).take(MAX_STDERR).read_to_end(&mut start);
+        let _ = io::copy(&mut reader, &mut io::sink());
+        String::from_utf8_lossy(&start).into_owned()
+    }));
+
+    let mut printed = false;
+    if let Some(stdout) = child.stdout.take() {
+        for (i, line) in BufReader::new(stdout).lines().enumerate() {
+            let Ok(line) = line else {
+                break;
+            };
+            printed = true;
+            if sender.unbounded_send(Output::Line { nr: i + 1, line }).is_err() {
+                // nobody listens anymore
+                break;
+            }
+        }
+    }
+
+    // pw-metadata -m and the like never exit on their own
+    let _ = child.kill();
+    let code = child.wait().ok().and_then(|status| status.code());
+    let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    (printed, LateError::Exit { program: program.to_string(), code, stderr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use iced::futures::executor;
+
+    #[test]
+    fn restarts_a_process_which_exits() {
+        const SCRIPT: &str = "echo first; echo second; echo 'no pipewire' >&2; exit 3";
+        let mut output = executor::block_on_stream(Box::pin(lines("sh", &["-c", SCRIPT])));
+        let mut next_line = || match output.next() {
+            Some(Output::Line { nr, line }) => format!("{nr}: {line}"),
+            Some(Output::Stopped(e)) => e.to_string(),
+            None => "end".to_string(),
+        };
+        assert_eq!(next_line(), "1: first");
+        assert_eq!(next_line(), "2: second");
+        assert_eq!(next_line(), "sh exited with code 3: no pipewire");
+        assert_eq!(next_line(), "1: first");
+    }
+
+    #[test]
+    fn stops_polling_once_dropped() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn unchanged() -> u32 {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            0
+        }
+
+        let mut values = executor::block_on_stream(Box::pin(poll(Duration::from_millis(10), unchanged)));
+        assert_eq!(values.next(), Some(0));
+        drop(values);
+        thread::sleep(Duration::from_millis(50));
+        let calls = CALLS.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(CALLS.load(Ordering::SeqCst), calls);
+    }
+}
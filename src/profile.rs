@@ -1,90 +1,200 @@
 
-use std::path::PathBuf;
-use std::fs::{self, File};
-use std::io::Write;
+use std::fmt;
+use std::path::Path;
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
 
-use crate::paths::{CONFIG_PATH, PROFILES_NAME};
+use crate::error::{self, LateError};
+use crate::storage::{self, PROFILES_NAME};
 
 /// Extra state which copies LateState::buffer_size and LateState::sample_rate
 /// in order to easily serialize and deserialize them.
 /// Serialization is meant for profiles a user may create.
 /// E.g: A recording profile (with low latency) and a mixing / everyday profile
 /// (with moderate latency allowing for larger buffer sizes)
-#[derive(Serialize, Deserialize)]
+/// Fields missing in the file (e.g. because they were added later) get their default.
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LateProfile {
     /// the name under which to store the profile
     pub name: String,
     /// the buffer size
     pub buffer_size: u32,
     /// the sample rate
-    pub sample_rate: u32
+    pub sample_rate: u32,
+    /// clock.min-quantum. None leaves it unchanged when applying the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_quantum: Option<u32>,
+    /// clock.max-quantum. None leaves it unchanged when applying the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_quantum: Option<u32>,
+    /// clock.allowed-rates. None leaves it unchanged when applying the profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_rates: Option<Vec<u32>>,
+    /// preferred over other profiles with the same values. at most one profile is the default
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_default: bool,
 }
 
-pub fn ensure_profiles_file() -> std::io::Result<PathBuf> {
-    let home_opt = home::home_dir();
-    if home_opt.is_some() {
-        let mut config = home_opt.unwrap();
-        config.push(CONFIG_PATH);
+/// Why a profile name can't be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    Empty,
+    /// another profile has this name already
+    Taken(String),
+    /// no profile has this name (anymore)
+    Unknown(String),
+}
 
-        // ensure we can fetch the config dir and exists state
-        let config_dir_exists = fs::exists(&config);
-        if config_dir_exists.is_err() {
-            return Err(config_dir_exists.err().unwrap());
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Empty => write!(f, "a profile needs a name"),
+            NameError::Taken(name) => write!(f, "there is a profile named '{name}' already"),
+            NameError::Unknown(name) => write!(f, "there is no profile named '{name}'"),
         }
+    }
+}
 
-        // ensure we have a config dir 
-        if !fs::exists(&config).unwrap() {
-            let result = fs::create_dir(&config);
-            if result.is_err() {
-                return Err(result.err().unwrap());
-            }
-        }
+impl std::error::Error for NameError {}
 
-        // ensure we can fetch the config file and its exists state
-        config.push(PROFILES_NAME);
+/// the version of the profiles file written by this version of late.
+/// version 0 is the bare array of profiles, written before the file had a version
+pub const PROFILES_VERSION: u32 = 1;
 
-        let config_file_exists = fs::exists(&config);
-        if config_file_exists.is_err() {
-            return Err(config_file_exists.err().unwrap());
-        }
+/// The content of the profiles file
+#[derive(Default, Serialize, Deserialize)]
+pub struct ProfilesDocument {
+    pub version: u32,
+    #[serde(default)]
+    pub profiles: Vec<LateProfile>,
+}
 
-        // ensure we have a config file
-        if !fs::exists(&config).unwrap() {
-            let result = File::create(&config);
-            if result.is_err() {
-                return Err(result.err().unwrap());
-            }
-        }
+/// The result of reading the profiles file
+pub struct ParsedProfiles {
+    pub profiles: Vec<LateProfile>,
+    /// the version of the file, if it was older than PROFILES_VERSION
+    pub migrated_from: Option<u32>,
+}
+
+/// parses the content of the profiles file, migrating older versions step by step
+pub fn parse_profiles(contents: &str) -> Result<ParsedProfiles, String> {
+    if contents.trim().is_empty() {
+        // the file was just created
+        return Ok(ParsedProfiles { profiles: Vec::new(), migrated_from: None });
+    }
+    let mut document: Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+    let file_version = match &document {
+        Value::Array(_) => 0,
+        _ => document["version"].as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or("the profiles file has no version")?,
+    };
+    if file_version > PROFILES_VERSION {
+        return Err(format!("the profiles file has version {}, this version of late only knows up to {}",
+            file_version, PROFILES_VERSION));
+    }
 
-        return Ok(config);
+    for version in file_version..PROFILES_VERSION {
+        document = migrate(version, document);
     }
-    Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "Cannot find home directory!"))
+    let mut document: ProfilesDocument = serde_json::from_value(document).map_err(|e| e.to_string())?;
+    make_names_unique(&mut document.profiles);
+    Ok(ParsedProfiles {
+        profiles: document.profiles,
+        migrated_from: (file_version < PROFILES_VERSION).then_some(file_version),
+    })
 }
 
-pub fn save_profiles(state: &Vec<LateProfile>) {
-    let serialized = serde_json::to_string(&state);
-    let config_file = match ensure_profiles_file(){
-        Ok(c) => c,
-        Err(e) => { 
-            print!("{}", e);
-            return;
-        }
+/// migrates document from version to version + 1
+fn migrate(version: u32, document: Value) -> Value {
+    match version {
+        // the bare array becomes the profiles of the document
+        0 => serde_json::json!({ "version": 1, "profiles": document }),
+        _ => document,
+    }
+}
+
+/// changes the saved profiles, holding the lock of the config dir from reading to writing them,
+/// so that changes another instance of late (or the CLI) made meanwhile are kept.
+/// nothing is written if change fails
+/// @returns the profiles as saved and what change returned
+pub fn update_profiles<T>(change: impl FnOnce(&mut Vec<LateProfile>) -> error::Result<T>)
+    -> error::Result<(Vec<LateProfile>, T)> {
+    update_profiles_in(&storage::config_dir()?, change)
+}
+
+/// changes the profiles saved in dir instead of the config dir, see update_profiles
+pub fn update_profiles_in<T>(dir: &Path, change: impl FnOnce(&mut Vec<LateProfile>) -> error::Result<T>)
+    -> error::Result<(Vec<LateProfile>, T)> {
+    let mut changed = None;
+    storage::update_in(dir, PROFILES_NAME, |contents| change_contents(dir, contents, change, &mut changed))?;
+    Ok(changed.expect("the profiles were changed"))
+}
+
+/// applies change to the profiles file in dir, which contains contents
+/// @returns the new contents, the profiles and what change returned are put into changed
+fn change_contents<T>(dir: &Path, contents: &str, change: impl FnOnce(&mut Vec<LateProfile>) -> error::Result<T>,
+    changed: &mut Option<(Vec<LateProfile>, T)>) -> error::Result<String> {
+    let mut profiles = match parse_file(dir, contents) {
+        Ok((profiles, _)) => profiles,
+        // a broken file is replaced once there is a copy of it, as load_profiles says.
+        // backup finds the copy made while parsing
+        Err(LateError::Parse { .. }) if storage::backup(&dir.join(PROFILES_NAME), "broken").is_ok() => Vec::new(),
+        Err(e) => return Err(e),
     };
+    let value = change(&mut profiles)?;
+    let serialized = serialize(&profiles);
+    *changed = Some((profiles, value));
+    Ok(serialized)
+}
 
-    let f = File::create(config_file);
-    write!(f.unwrap(), "{}", serialized.unwrap())
-        .expect("Could not write profiles to file!");
+/// @returns the position of the profile called name
+pub fn position(profiles: &[LateProfile], name: &str) -> Result<usize, NameError> {
+    profiles.iter().position(|p| p.name == name).ok_or(NameError::Unknown(name.to_string()))
+}
 
+fn serialize(profiles: &[LateProfile]) -> String {
+    serde_json::to_string(&ProfilesDocument {
+        version: PROFILES_VERSION,
+        profiles: profiles.to_vec(),
+    }).expect("profiles only hold values JSON can represent")
 }
 
-pub fn load_profiles() -> Vec<LateProfile> {
-    let config_path = ensure_profiles_file().unwrap_or_default();
-    let file_contents = fs::read_to_string(config_path)
-        .expect("Could not read profiles file!");
-    serde_json::from_str(&file_contents).unwrap_or(vec![])
+/// reads the profiles file. older versions are migrated, after backing up the file.
+/// a file which can't be read is backed up as well, since the next save overwrites it
+pub fn load_profiles() -> error::Result<Vec<LateProfile>> {
+    load_profiles_in(&storage::config_dir()?)
+}
+
+fn load_profiles_in(dir: &Path) -> error::Result<Vec<LateProfile>> {
+    let (profiles, migrated) = parse_file(dir, &storage::read_in(dir, PROFILES_NAME)?)?;
+    if !migrated {
+        return Ok(profiles);
+    }
+    // migrated again under the lock, in case another instance of late wrote the file meanwhile
+    Ok(update_profiles_in(dir, |_| Ok(()))?.0)
+}
+
+/// parses the contents of the profiles file in dir, backing it up if it is old or broken
+/// @returns the profiles and whether they were migrated
+fn parse_file(dir: &Path, file_contents: &str) -> error::Result<(Vec<LateProfile>, bool)> {
+    let config_path = dir.join(PROFILES_NAME);
+    match parse_profiles(file_contents) {
+        Ok(ParsedProfiles { profiles, migrated_from: None }) => Ok((profiles, false)),
+        Ok(ParsedProfiles { profiles, migrated_from: Some(version) }) => {
+            // without a backup, the old file is kept and migrated again next time
+            storage::backup(&config_path, &format!("v{}", version)).map_err(|e| LateError::io(&config_path, e))?;
+            Ok((profiles, true))
+        }
+        Err(e) => {
+            let copy = match storage::backup(&config_path, "broken") {
+                Ok(backup) => format!("saved a copy as {}", backup.display()),
+                Err(e) => format!("could not back it up: {}", e),
+            };
+            Err(LateError::Parse { what: config_path.display().to_string(), message: format!("{} ({})", e, copy) })
+        }
+    }
 }
 
 pub fn get_profile_names(profiles: &Vec<LateProfile>) -> Vec<String> {
@@ -99,12 +209,7 @@ pub fn get_profile_names(profiles: &Vec<LateProfile>) -> Vec<String> {
 pub fn choose_profile(profiles: &Vec<LateProfile>, name: &str) -> Option<LateProfile> {
     for profile in profiles {
         if profile.name == name {
-            let copy = LateProfile {
-                name: profile.name.clone(),
-                sample_rate: profile.sample_rate,
-                buffer_size: profile.buffer_size
-            };
-            return Some(copy);
+            return Some(profile.clone());
         }
     }
     None
@@ -117,16 +222,225 @@ pub fn remove_profile(profiles: &mut Vec<LateProfile>, name: &str) {
     }
 }
 
-pub fn get_current_if_any(profiles: &Vec<LateProfile>, sample_rate: Option<u32>, buffer_size: Option<u32>) -> Option<String> {
+pub fn get_current_if_any(profiles: &[LateProfile], sample_rate: Option<u32>, buffer_size: Option<u32>) -> Option<String> {
     let s = sample_rate.unwrap_or(0);
     let b = buffer_size.unwrap_or(0);
-    for profile in profiles {
-        if profile.sample_rate == s
-            && profile.buffer_size == b {
-            // if there are multiple profiles with the same name, we return the first one.
-            // there is no way to know which one the user wanted.
-            return Some(profile.name.clone());
+    let mut matching = profiles.iter()
+        .filter(|profile| profile.sample_rate == s && profile.buffer_size == b);
+    // if there are multiple profiles with the same values, the default one wins.
+    // otherwise there is no way to know which one the user wanted, so we return the first one.
+    let first = matching.next()?;
+    let default = std::iter::once(first).chain(matching).find(|profile| profile.is_default);
+    Some(default.unwrap_or(first).name.clone())
+}
+
+/// checks that name can be given to the profile at index (None for a new profile)
+pub fn check_name(profiles: &[LateProfile], name: &str, index: Option<usize>) -> Result<(), NameError> {
+    if name.trim().is_empty() {
+        return Err(NameError::Empty);
+    }
+    let taken = profiles.iter().enumerate()
+        .any(|(i, profile)| Some(i) != index && profile.name == name);
+    if taken {
+        return Err(NameError::Taken(name.to_string()));
+    }
+    Ok(())
+}
+
+/// adds profile, unless its name is taken
+pub fn add_profile(profiles: &mut Vec<LateProfile>, profile: LateProfile) -> Result<(), NameError> {
+    check_name(profiles, &profile.name, None)?;
+    profiles.push(profile);
+    Ok(())
+}
+
+/// replaces the values of the profile with the same name, or adds profile if there is none.
+/// the replaced profile keeps its position and whether it is the default
+pub fn upsert_profile(profiles: &mut Vec<LateProfile>, profile: LateProfile) -> Result<(), NameError> {
+    match profiles.iter_mut().find(|p| p.name == profile.name) {
+        Some(existing) => {
+            let is_default = existing.is_default;
+            *existing = LateProfile { is_default, ..profile };
+            Ok(())
         }
+        None => add_profile(profiles, profile),
+    }
+}
+
+pub fn rename_profile(profiles: &mut [LateProfile], index: usize, name: &str) -> Result<(), NameError> {
+    check_name(profiles, name, Some(index))?;
+    profiles[index].name = name.to_string();
+    Ok(())
+}
+
+/// inserts a copy of the profile at index right after it
+/// @returns the index of the copy
+pub fn duplicate_profile(profiles: &mut Vec<LateProfile>, index: usize) -> usize {
+    let copy = LateProfile {
+        name: free_name(profiles, &profiles[index].name),
+        is_default: false,
+        ..profiles[index].clone()
+    };
+    profiles.insert(index + 1, copy);
+    index + 1
+}
+
+/// moves the profile at index to the position to, shifting the ones in between
+pub fn move_profile(profiles: &mut Vec<LateProfile>, index: usize, to: usize) {
+    if index < profiles.len() && to < profiles.len() {
+        let profile = profiles.remove(index);
+        profiles.insert(to, profile);
+    }
+}
+
+/// makes the profile at index the default. None leaves no default
+pub fn set_default(profiles: &mut [LateProfile], index: Option<usize>) {
+    for (i, profile) in profiles.iter_mut().enumerate() {
+        profile.is_default = Some(i) == index;
+    }
+}
+
+/// @returns "name (2)", "name (3)", ... whichever is not taken yet
+pub fn free_name(profiles: &[LateProfile], name: &str) -> String {
+    (2..)
+        .map(|i| format!("{} ({})", name, i))
+        .find(|candidate| profiles.iter().all(|p| &p.name != candidate))
+        .expect("there is always a free name")
+}
+
+/// renames profiles whose name was used before, e.g. in files written by older versions
+fn make_names_unique(profiles: &mut [LateProfile]) {
+    for i in 1..profiles.len() {
+        if check_name(&profiles[..i], &profiles[i].name, None) == Err(NameError::Taken(profiles[i].name.clone())) {
+            profiles[i].name = free_name(profiles, &profiles[i].name);
+        }
+    }
+    // only one default
+    if let Some(first) = profiles.iter().position(|p| p.is_default) {
+        set_default(profiles, Some(first));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    static V0: &str = include_str!("../tests/fixtures/profiles/v0.json");
+    static V1: &str = include_str!("../tests/fixtures/profiles/v1.json");
+    static BROKEN: &str = include_str!("../tests/fixtures/profiles/broken.json");
+    static FUTURE: &str = include_str!("../tests/fixtures/profiles/future.json");
+
+    #[test]
+    fn migrates_bare_array() {
+        let parsed = parse_profiles(V0).unwrap();
+        assert_eq!(parsed.migrated_from, Some(0));
+        assert_eq!(get_profile_names(&parsed.profiles), vec!["Recording", "Mixing"]);
+        assert_eq!(parsed.profiles[1].max_quantum, Some(2048));
+    }
+
+    #[test]
+    fn reads_current_version_with_missing_fields() {
+        let parsed = parse_profiles(V1).unwrap();
+        assert_eq!(parsed.migrated_from, None);
+        assert_eq!(parsed.profiles[1].buffer_size, 256);
+        assert_eq!(parsed.profiles[1].sample_rate, 0);
+        assert!(parse_profiles("").unwrap().profiles.is_empty());
+    }
+
+    fn profile(name: &str, buffer_size: u32) -> LateProfile {
+        LateProfile { name: name.to_string(), buffer_size, sample_rate: 48000, ..Default::default() }
+    }
+
+    #[test]
+    fn enforces_unique_names() {
+        let mut profiles = vec![profile("Recording", 64), profile("Mixing", 1024)];
+        assert_eq!(add_profile(&mut profiles, profile("Mixing", 512)), Err(NameError::Taken("Mixing".to_string())));
+        assert_eq!(rename_profile(&mut profiles, 0, "Mixing"), Err(NameError::Taken("Mixing".to_string())));
+        assert_eq!(rename_profile(&mut profiles, 0, " "), Err(NameError::Empty));
+        assert_eq!(rename_profile(&mut profiles, 0, "Recording"), Ok(()));
+
+        upsert_profile(&mut profiles, profile("Recording", 128)).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles[0].buffer_size, 128);
+
+        assert_eq!(duplicate_profile(&mut profiles, 0), 1);
+        assert_eq!(get_profile_names(&profiles), vec!["Recording", "Recording (2)", "Mixing"]);
+        move_profile(&mut profiles, 2, 0);
+        assert_eq!(get_profile_names(&profiles), vec!["Mixing", "Recording", "Recording (2)"]);
+    }
+
+    #[test]
+    fn default_wins_among_equal_profiles() {
+        let mut profiles = vec![profile("Recording", 64), profile("Tracking", 64)];
+        assert_eq!(get_current_if_any(&profiles, Some(48000), Some(64)).as_deref(), Some("Recording"));
+        set_default(&mut profiles, Some(1));
+        assert_eq!(get_current_if_any(&profiles, Some(48000), Some(64)).as_deref(), Some("Tracking"));
+        set_default(&mut profiles, Some(0));
+        assert!(!profiles[1].is_default);
+    }
+
+    #[test]
+    fn renames_duplicates_when_loading() {
+        let parsed = parse_profiles(r#"[{"name":"A"},{"name":"A"},{"name":"A (2)"}]"#).unwrap();
+        assert_eq!(get_profile_names(&parsed.profiles), vec!["A", "A (3)", "A (2)"]);
+    }
+
+    #[test]
+    fn rejects_broken_and_newer_files() {
+        assert!(parse_profiles(BROKEN).is_err());
+        assert!(parse_profiles(FUTURE).is_err_and(|e| e.contains("version 99")));
+    }
+
+    #[test]
+    fn backs_up_broken_file_once_and_migrates_old_one() {
+        let dir = std::env::temp_dir().join(format!("late-profiles-{}", std::process::id()));
+        let backups = |reason: &str| fs::read_dir(&dir).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy()
+                .starts_with(&format!("{}.{}.", PROFILES_NAME, reason)))
+            .count();
+
+        storage::write_in(&dir, PROFILES_NAME, BROKEN).unwrap();
+        assert!(load_profiles_in(&dir).is_err());
+        assert!(load_profiles_in(&dir).is_err());
+        assert_eq!(backups("broken"), 1);
+        // the file is left for the user to fix
+        assert_eq!(storage::read_in(&dir, PROFILES_NAME).unwrap(), BROKEN);
+
+        storage::write_in(&dir, PROFILES_NAME, V0).unwrap();
+        assert_eq!(get_profile_names(&load_profiles_in(&dir).unwrap()), vec!["Recording", "Mixing"]);
+        assert_eq!(backups("v0"), 1);
+        let saved = parse_profiles(&storage::read_in(&dir, PROFILES_NAME).unwrap()).unwrap();
+        assert_eq!(saved.migrated_from, None);
+        assert_eq!(saved.profiles[1].max_quantum, Some(2048));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn updates_start_from_what_is_saved() {
+        let dir = std::env::temp_dir().join(format!("late-profiles-update-{}", std::process::id()));
+        let add = |name: &str| update_profiles_in(&dir, |profiles| Ok(add_profile(profiles, profile(name, 64))?));
+
+        // e.g. the CLI and the GUI, each adding one
+        add("Recording").unwrap();
+        let (profiles, ()) = add("Mixing").unwrap();
+        assert_eq!(get_profile_names(&profiles), vec!["Recording", "Mixing"]);
+
+        let gone = update_profiles_in(&dir, |profiles| {
+            profiles.clear();
+            Ok(position(profiles, "Live")?)
+        });
+        assert!(matches!(gone, Err(LateError::Name(NameError::Unknown(_)))));
+        assert_eq!(get_profile_names(&load_profiles_in(&dir).unwrap()), vec!["Recording", "Mixing"]);
+
+        // a broken file is replaced once it is backed up
+        storage::write_in(&dir, PROFILES_NAME, BROKEN).unwrap();
+        assert_eq!(get_profile_names(&add("Live").unwrap().0), vec!["Live"]);
+        assert_eq!(fs::read_dir(&dir).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".bak"))
+            .count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
     }
-    None
 }
@@ -0,0 +1,222 @@
+// sharing profiles between machines.
+// profiles are exported into a standalone file in the same format as the profiles file,
+// either as JSON or as TOML (by extension), e.g.
+/*
+* version = 1
+*
+* [[profiles]]
+* name = "Recording"
+* buffer_size = 64
+* sample_rate = 48000
+*/
+// importing merges them into the saved profiles. names which exist already are
+// renamed, overwritten or skipped.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::profile::{self, LateProfile, ProfilesDocument, PROFILES_VERSION};
+
+/// What to do with an imported profile whose name exists already
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// import it as "Name (2)"
+    #[default]
+    Rename,
+    /// replace the saved profile
+    Overwrite,
+    /// keep the saved profile
+    Skip,
+}
+
+impl OnConflict {
+    pub const ALL: [OnConflict; 3] = [OnConflict::Rename, OnConflict::Overwrite, OnConflict::Skip];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rename" => Some(OnConflict::Rename),
+            "overwrite" => Some(OnConflict::Overwrite),
+            "skip" => Some(OnConflict::Skip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OnConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            OnConflict::Rename => "Rename",
+            OnConflict::Overwrite => "Overwrite",
+            OnConflict::Skip => "Skip",
+        })
+    }
+}
+
+/// What an import did
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub added: Vec<String>,
+    /// (name in the file, name it was saved as)
+    pub renamed: Vec<(String, String)>,
+    pub overwritten: Vec<String>,
+    pub skipped: Vec<String>,
+    /// names which can't be used, with the reason
+    pub rejected: Vec<String>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = vec![format!("{} added", self.added.len())];
+        if !self.renamed.is_empty() {
+            let renamed: Vec<String> = self.renamed.iter().map(|(from, to)| format!("{from} as {to}")).collect();
+            parts.push(format!("{} renamed ({})", self.renamed.len(), renamed.join(", ")));
+        }
+        if !self.overwritten.is_empty() {
+            parts.push(format!("{} overwritten ({})", self.overwritten.len(), self.overwritten.join(", ")));
+        }
+        if !self.skipped.is_empty() {
+            parts.push(format!("{} skipped ({})", self.skipped.len(), self.skipped.join(", ")));
+        }
+        if !self.rejected.is_empty() {
+            parts.push(format!("{} rejected ({})", self.rejected.len(), self.rejected.join(", ")));
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("toml"))
+}
+
+/// renders profiles as JSON or, if path ends with .toml, as TOML
+pub fn render(profiles: &[LateProfile], path: &Path) -> io::Result<String> {
+    let document = ProfilesDocument { version: PROFILES_VERSION, profiles: profiles.to_vec() };
+    if is_toml(path) {
+        toml::to_string(&document).map_err(io::Error::other)
+    } else {
+        serde_json::to_string_pretty(&document).map_err(io::Error::other)
+    }
+}
+
+/// parses an exported file. JSON files may be in any version of the profiles file
+pub fn parse(contents: &str, path: &Path) -> io::Result<Vec<LateProfile>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    if is_toml(path) {
+        let document: ProfilesDocument = toml::from_str(contents).map_err(|e| invalid(e.to_string()))?;
+        if document.version > PROFILES_VERSION {
+            return Err(invalid(format!("{} has version {}, this version of late only knows up to {}",
+                path.display(), document.version, PROFILES_VERSION)));
+        }
+        Ok(document.profiles)
+    } else {
+        profile::parse_profiles(contents).map(|parsed| parsed.profiles).map_err(invalid)
+    }
+}
+
+/// writes profiles to path, which has to end with .json or .toml
+pub fn export(profiles: &[LateProfile], path: &Path) -> io::Result<()> {
+    let known = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json") || e.eq_ignore_ascii_case("toml"));
+    if !known {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "profiles can only be exported to .json or .toml files"));
+    }
+    fs::write(path, render(profiles, path)?)
+}
+
+pub fn read(path: &Path) -> io::Result<Vec<LateProfile>> {
+    parse(&fs::read_to_string(path)?, path)
+}
+
+/// adds imported to profiles, resolving equal names as on_conflict says.
+/// profiles whose names can't be used (e.g. empty ones) are left out.
+/// names are unique afterwards, as long as they were before
+pub fn merge(profiles: &mut Vec<LateProfile>, imported: Vec<LateProfile>, on_conflict: OnConflict) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    for mut new in imported {
+        match profile::check_name(profiles, &new.name, None) {
+            Ok(()) | Err(profile::NameError::Taken(_)) => {}
+            Err(e) => {
+                summary.rejected.push(format!("'{}': {}", new.name, e));
+                continue;
+            }
+        }
+        // the default is chosen on each machine
+        new.is_default = false;
+        let existing = profiles.iter().position(|p| p.name == new.name);
+        match (existing, on_conflict) {
+            (None, _) => {
+                summary.added.push(new.name.clone());
+                profiles.push(new);
+            }
+            (Some(_), OnConflict::Skip) => summary.skipped.push(new.name),
+            (Some(i), OnConflict::Overwrite) => {
+                summary.overwritten.push(new.name.clone());
+                profiles[i] = LateProfile { is_default: profiles[i].is_default, ..new };
+            }
+            (Some(_), OnConflict::Rename) => {
+                let name = profile::free_name(profiles, &new.name);
+                summary.renamed.push((std::mem::replace(&mut new.name, name.clone()), name));
+                profiles.push(new);
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, buffer_size: u32) -> LateProfile {
+        LateProfile { name: name.to_string(), buffer_size, sample_rate: 48000, ..Default::default() }
+    }
+
+    #[test]
+    fn round_trips_json_and_toml() {
+        let profiles = vec![
+            profile("Recording", 64),
+            LateProfile { allowed_rates: Some(vec![44100, 48000]), ..profile("Mixing", 1024) },
+        ];
+        for file in ["shared.json", "shared.toml"] {
+            let path = Path::new(file);
+            let parsed = parse(&render(&profiles, path).unwrap(), path).unwrap();
+            assert_eq!(profile::get_profile_names(&parsed), vec!["Recording", "Mixing"]);
+            assert_eq!(parsed[1].allowed_rates, Some(vec![44100, 48000]));
+        }
+        assert!(render(&profiles, Path::new("shared.toml")).unwrap().starts_with("version = 1"));
+    }
+
+    #[test]
+    fn resolves_conflicts() {
+        let saved = vec![profile("Recording", 64), profile("Recording (2)", 128)];
+        let imported = || vec![profile("Recording", 256), profile("Mixing", 1024)];
+
+        let mut profiles = saved.clone();
+        let summary = merge(&mut profiles, imported(), OnConflict::Rename);
+        assert_eq!(summary.renamed, vec![("Recording".to_string(), "Recording (3)".to_string())]);
+        assert_eq!(profiles[2].buffer_size, 256);
+
+        let mut profiles = saved.clone();
+        merge(&mut profiles, imported(), OnConflict::Overwrite);
+        assert_eq!(profiles[0].buffer_size, 256);
+        assert_eq!(profiles.len(), 3);
+
+        let mut profiles = saved.clone();
+        let summary = merge(&mut profiles, imported(), OnConflict::Skip);
+        assert_eq!(summary.skipped, vec!["Recording"]);
+        assert_eq!(summary.added, vec!["Mixing"]);
+        assert_eq!(profiles[0].buffer_size, 64);
+    }
+
+    #[test]
+    fn rejects_invalid_names_and_formats() {
+        let mut profiles = vec![profile("Recording", 64)];
+        let summary = merge(&mut profiles, vec![profile(" ", 128), profile("Mixing", 1024)], OnConflict::Rename);
+        assert_eq!(summary.to_string(), "1 added, 1 rejected (' ': a profile needs a name)");
+        assert_eq!(profile::get_profile_names(&profiles), vec!["Recording", "Mixing"]);
+
+        let error = export(&profiles, Path::new("shared.yaml")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,93 @@
+
+// access to the objects reported by pw-dump.
+// pw-dump prints a JSON array of all objects in the pipewire graph, e.g.
+/*
+* [ { "id": 31, "type": "PipeWire:Interface:Metadata",
+*     "props": { "metadata.name": "settings" },
+*     "metadata": [ { "subject": 0, "key": "clock.rate", "type": "", "value": 48000 } ] },
+*   { "id": 55, "type": "PipeWire:Interface:Node",
+*     "info": { "state": "running", "props": { ... }, "params": { "EnumFormat": [ ... ] } } } ]
+*/
+
+use std::process::Command;
+use serde_json::Value;
+
+use crate::error::{LateError, Result};
+
+static NODE_TYPE: &str = "PipeWire:Interface:Node";
+static METADATA_TYPE: &str = "PipeWire:Interface:Metadata";
+
+/// runs pw-dump and parses its output
+pub fn dump() -> Result<Vec<Value>> {
+    let output = Command::new("pw-dump")
+        .output()
+        .map_err(|source| LateError::Spawn { program: "pw-dump".to_string(), source })?;
+    if !output.status.success() {
+        return Err(LateError::Exit {
+            program: "pw-dump".to_string(),
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        });
+    }
+    parse(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|e| LateError::Parse { what: "the output of pw-dump".to_string(), message: e.to_string() })
+}
+
+pub fn parse(json: &str) -> serde_json::Result<Vec<Value>> {
+    serde_json::from_str(json)
+}
+
+/// @returns the value of key (for subject 0) in the metadata object called metadata_name
+pub fn metadata_value<'a>(objects: &'a [Value], metadata_name: &str, key: &str) -> Option<&'a Value> {
+    objects.iter()
+        .filter(|o| o["type"] == METADATA_TYPE && o["props"]["metadata.name"] == metadata_name)
+        .filter_map(|o| o["metadata"].as_array())
+        .flatten()
+        .find(|entry| entry["subject"] == 0 && entry["key"] == key)
+        .map(|entry| &entry["value"])
+}
+
+/// A node object of the dump
+pub struct Node<'a> {
+    info: &'a Value,
+}
+
+impl<'a> Node<'a> {
+    /// e.g. "running", "idle" or "suspended"
+    pub fn state(&self) -> Option<&'a str> {
+        self.info["state"].as_str()
+    }
+
+    pub fn prop(&self, key: &str) -> &'a Value {
+        &self.info["props"][key]
+    }
+
+    pub fn prop_str(&self, key: &str) -> Option<&'a str> {
+        self.prop(key).as_str()
+    }
+
+    pub fn prop_u32(&self, key: &str) -> Option<u32> {
+        value_as_u32(self.prop(key))
+    }
+
+    /// @returns all entries of the param (e.g. "EnumFormat") the node reported
+    pub fn params(&self, name: &str) -> &'a [Value] {
+        self.info["params"][name].as_array().map_or(&[], |v| v.as_slice())
+    }
+}
+
+/// @returns all node objects of the dump
+pub fn nodes(objects: &[Value]) -> impl Iterator<Item = Node<'_>> {
+    objects.iter()
+        .filter(|o| o["type"] == NODE_TYPE)
+        .map(|o| Node { info: &o["info"] })
+}
+
+/// pipewire reports numbers either as JSON numbers or as strings, depending on where they come from
+pub fn value_as_u32(value: &Value) -> Option<u32> {
+    match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
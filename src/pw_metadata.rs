@@ -0,0 +1,228 @@
+
+// parser for the output of pw-metadata.
+// pw-metadata prints one line per metadata property, e.g.
+/*
+* Found "settings" metadata 31
+* update: id:0 key:'clock.force-quantum' value:'128' type:''
+* update: id:0 key:'default.audio.sink' value:'{"name":"alsa_output..."}' type:'Spa:String:JSON'
+* remove: id:0 key:'clock.force-rate'
+* remove: id:0 all keys
+*/
+// a dump (or a monitor session) may contain several lines for the same key,
+// the last one wins.
+
+use std::fmt;
+
+/// A single property of a pipewire metadata object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataEntry {
+    /// the subject the property belongs to. the global settings use 0
+    pub id: u32,
+    pub key: String,
+    /// the raw value, without the surrounding quotes
+    pub value: String,
+    /// e.g. "Spa:String:JSON". empty for untyped values
+    pub value_type: String,
+}
+
+/// One line of pw-metadata output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataLine {
+    /// the header naming the metadata object
+    Found,
+    Update(MetadataEntry),
+    /// a removed key. None means all keys of the subject were removed
+    Remove { id: u32, key: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// a line did not start with any of the known prefixes
+    UnknownLine { line: usize, content: String },
+    /// a line was missing a field, e.g. because the output got truncated
+    MissingField { line: usize, field: &'static str },
+    /// the id of a line was not a number
+    InvalidId { line: usize, id: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownLine { line, content } =>
+                write!(f, "line {line}: unexpected pw-metadata output '{content}'"),
+            ParseError::MissingField { line, field } =>
+                write!(f, "line {line}: missing field '{field}'"),
+            ParseError::InvalidId { line, id } =>
+                write!(f, "line {line}: invalid id '{id}'"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// parses a single line. line_nr is only used for error messages
+/// @returns None for empty lines
+pub fn parse_line(line: &str, line_nr: usize) -> Result<Option<MetadataLine>, ParseError> {
+    let line = line.trim_end();
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    if line.starts_with("Found ") {
+        return Ok(Some(MetadataLine::Found));
+    }
+    if let Some(rest) = line.strip_prefix("update: ") {
+        let (id, rest) = parse_id(rest, line_nr)?;
+        let rest = rest.strip_prefix("key:'")
+            .ok_or(ParseError::MissingField { line: line_nr, field: "key" })?;
+        let (key, rest) = rest.split_once("' value:'")
+            .ok_or(ParseError::MissingField { line: line_nr, field: "value" })?;
+        // values may contain quotes themselves (e.g. JSON), so search the type from the end
+        let (value, value_type) = rest.rsplit_once("' type:'")
+            .ok_or(ParseError::MissingField { line: line_nr, field: "type" })?;
+        let value_type = value_type.strip_suffix('\'')
+            .ok_or(ParseError::MissingField { line: line_nr, field: "type" })?;
+
+        return Ok(Some(MetadataLine::Update(MetadataEntry {
+            id,
+            key: key.to_string(),
+            value: value.to_string(),
+            value_type: value_type.to_string(),
+        })));
+    }
+    if let Some(rest) = line.strip_prefix("remove: ") {
+        let (id, rest) = parse_id(rest, line_nr)?;
+        if rest == "all keys" {
+            return Ok(Some(MetadataLine::Remove { id, key: None }));
+        }
+        let key = rest.strip_prefix("key:'")
+            .and_then(|k| k.strip_suffix('\''))
+            .ok_or(ParseError::MissingField { line: line_nr, field: "key" })?;
+        return Ok(Some(MetadataLine::Remove { id, key: Some(key.to_string()) }));
+    }
+
+    Err(ParseError::UnknownLine { line: line_nr, content: line.to_string() })
+}
+
+/// splits "id:<n> <rest>" into n and rest
+fn parse_id(s: &str, line_nr: usize) -> Result<(u32, &str), ParseError> {
+    let s = s.strip_prefix("id:")
+        .ok_or(ParseError::MissingField { line: line_nr, field: "id" })?;
+    let (id, rest) = s.split_once(' ').unwrap_or((s, ""));
+    let id = id.parse()
+        .map_err(|_| ParseError::InvalidId { line: line_nr, id: id.to_string() })?;
+    Ok((id, rest))
+}
+
+/// parses the whole output of a pw-metadata call.
+/// @returns the properties that are set after applying all lines in order
+pub fn parse(output: &str) -> Result<Vec<MetadataEntry>, ParseError> {
+    let mut entries: Vec<MetadataEntry> = Vec::new();
+
+    for (i, line) in output.lines().enumerate() {
+        match parse_line(line, i + 1)? {
+            None | Some(MetadataLine::Found) => {}
+            Some(MetadataLine::Update(entry)) => {
+                match entries.iter_mut().find(|e| e.id == entry.id && e.key == entry.key) {
+                    Some(existing) => *existing = entry,
+                    None => entries.push(entry),
+                }
+            }
+            Some(MetadataLine::Remove { id, key }) => {
+                entries.retain(|e| e.id != id
+                    || key.as_ref().is_some_and(|k| *k != e.key));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// @returns the entry for key of subject id, if it is set
+pub fn find<'a>(entries: &'a [MetadataEntry], id: u32, key: &str) -> Option<&'a MetadataEntry> {
+    entries.iter().find(|e| e.id == id && e.key == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SETTINGS_DUMP: &str = include_str!("../tests/fixtures/pw-metadata/settings_dump.txt");
+    static FORCE_QUANTUM: &str = include_str!("../tests/fixtures/pw-metadata/force_quantum.txt");
+    static KEY_UNSET: &str = include_str!("../tests/fixtures/pw-metadata/key_unset.txt");
+    static DEFAULT_JSON: &str = include_str!("../tests/fixtures/pw-metadata/default_json.txt");
+    static MONITOR: &str = include_str!("../tests/fixtures/pw-metadata/monitor.txt");
+    static REMOVE_ALL: &str = include_str!("../tests/fixtures/pw-metadata/remove_all.txt");
+    static TRUNCATED: &str = include_str!("../tests/fixtures/pw-metadata/truncated.txt");
+
+    #[test]
+    fn parses_full_settings_dump() {
+        let entries = parse(SETTINGS_DUMP).unwrap();
+        assert_eq!(entries.len(), 8);
+
+        let rates = find(&entries, 0, "clock.allowed-rates").unwrap();
+        assert_eq!(rates.value, "[ 44100 48000 ]");
+        assert_eq!(rates.value_type, "");
+
+        let quantum = find(&entries, 0, "clock.force-quantum").unwrap();
+        assert_eq!(quantum.value, "128");
+    }
+
+    #[test]
+    fn parses_single_key() {
+        let entries = parse(FORCE_QUANTUM).unwrap();
+        assert_eq!(entries, vec![MetadataEntry {
+            id: 0,
+            key: "clock.force-quantum".to_string(),
+            value: "128".to_string(),
+            value_type: "".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn unset_key_is_missing_not_an_error() {
+        let entries = parse(KEY_UNSET).unwrap();
+        assert!(entries.is_empty());
+        assert!(find(&entries, 0, "clock.force-quantum").is_none());
+    }
+
+    #[test]
+    fn keeps_json_values_intact() {
+        let entries = parse(DEFAULT_JSON).unwrap();
+        let sink = find(&entries, 0, "default.audio.sink").unwrap();
+        assert_eq!(sink.value, r#"{"name":"alsa_output.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo"}"#);
+        assert_eq!(sink.value_type, "Spa:String:JSON");
+
+        // quotes inside the value must not end it
+        let target = find(&entries, 52, "target.object").unwrap();
+        assert_eq!(target.value, r#"{ "name": "Tim's Loopback" }"#);
+    }
+
+    #[test]
+    fn later_lines_win_and_removes_apply() {
+        let entries = parse(MONITOR).unwrap();
+        assert_eq!(find(&entries, 0, "clock.force-quantum").unwrap().value, "64");
+        assert!(find(&entries, 0, "clock.force-rate").is_none());
+    }
+
+    #[test]
+    fn remove_all_keys() {
+        let entries = parse(REMOVE_ALL).unwrap();
+        assert!(find(&entries, 0, "clock.force-quantum").is_none());
+        assert_eq!(find(&entries, 0, "clock.force-rate").unwrap().value, "96000");
+    }
+
+    #[test]
+    fn truncated_output_is_an_error() {
+        assert_eq!(parse(TRUNCATED), Err(ParseError::MissingField { line: 2, field: "type" }));
+    }
+
+    #[test]
+    fn unknown_output_is_an_error() {
+        assert_eq!(parse("Error: no such metadata"), Err(ParseError::UnknownLine {
+            line: 1,
+            content: "Error: no such metadata".to_string(),
+        }));
+        assert_eq!(
+            parse_line("update: id:x key:'a' value:'b' type:''", 3),
+            Err(ParseError::InvalidId { line: 3, id: "x".to_string() }));
+    }
+}
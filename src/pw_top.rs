@@ -0,0 +1,205 @@
+// parser for the batch output of pw-top (pw-top -b).
+// pw-top prints a table of all nodes once per second, each one starting with the header, e.g.
+/*
+* S   ID  QUANT   RATE    WAIT    BUSY   W/Q   B/Q  ERR FORMAT           NAME
+* R   46    256  48000  37.2us   5.3us  0.01  0.00    2    S32LE 2 48000 alsa_output.usb-...
+* R   71    256  48000  12.1us  26.3us  0.00  0.01    1    F32LE 2 48000  + Firefox
+*/
+// followers are listed below their driver, with their name prefixed by "+".
+// ERR counts the xruns since pw-top started.
+
+use std::collections::VecDeque;
+
+use iced::futures::{future, Stream, StreamExt};
+
+use crate::process_stream::{self, Output};
+
+/// how many updates the xrun history keeps, i.e. about a minute
+const HISTORY_LEN: usize = 60;
+
+/// One driver of the graph, as reported by pw-top
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DriverStats {
+    pub id: u32,
+    pub name: String,
+    /// e.g. 'R' for running, 'S' for suspended
+    pub state: char,
+    pub quantum: Option<u32>,
+    pub rate: Option<u32>,
+    /// the time the graph was busy, as fraction of the quantum (B/Q)
+    pub busy: Option<f32>,
+    /// xruns of the driver and all of its followers
+    pub errors: u32,
+}
+
+impl DriverStats {
+    pub fn is_running(&self) -> bool {
+        self.state == 'R'
+    }
+}
+
+/// One line of pw-top output
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopLine {
+    /// starts a new table
+    Header,
+    Driver(DriverStats),
+    /// a node driven by the previous driver, only its xruns are of interest
+    Follower { errors: u32 },
+}
+
+/// parses a single line. @returns None for empty and unknown lines
+pub fn parse_line(line: &str) -> Option<TopLine> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.first() == Some(&"S") && fields.get(1) == Some(&"ID") {
+        return Some(TopLine::Header);
+    }
+    if fields.len() < 9 {
+        return None;
+    }
+
+    let errors = fields[8].parse().ok()?;
+    let rest = &fields[9..];
+    if rest.contains(&"+") {
+        return Some(TopLine::Follower { errors });
+    }
+
+    let number = |s: &str| s.parse().ok().filter(|n| *n != 0);
+    Some(TopLine::Driver(DriverStats {
+        id: fields[1].parse().ok()?,
+        // the format may consist of several words, the node name never does
+        name: rest.last().unwrap_or(&"").to_string(),
+        state: fields[0].chars().next()?,
+        quantum: number(fields[2]),
+        rate: number(fields[3]),
+        busy: fields[7].parse().ok(),
+        errors,
+    }))
+}
+
+/// collects lines into complete tables
+#[derive(Debug, Default)]
+pub struct TableParser {
+    drivers: Vec<DriverStats>,
+    /// whether a header was seen, i.e. the table is complete up to the next one
+    started: bool,
+}
+
+impl TableParser {
+    /// @returns the drivers of the previous table, once the next one starts
+    pub fn push(&mut self, line: &str) -> Option<Vec<DriverStats>> {
+        match parse_line(line)? {
+            TopLine::Header => {
+                let table = std::mem::take(&mut self.drivers);
+                let was_started = std::mem::replace(&mut self.started, true);
+                was_started.then_some(table)
+            }
+            TopLine::Driver(driver) => {
+                self.drivers.push(driver);
+                None
+            }
+            TopLine::Follower { errors } => {
+                if let Some(driver) = self.drivers.last_mut() {
+                    driver.errors += errors;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// @returns a stream of the drivers, updated about once per second
+pub fn watch() -> impl Stream<Item = Vec<DriverStats>> {
+    let mut parser = TableParser::default();
+    process_stream::lines("pw-top", &["-b"])
+        .filter_map(move |output| future::ready(match output {
+            Output::Line { line, .. } => parser.push(&line),
+            // a half read table would mix up the drivers of both runs
+            Output::Stopped(_) => {
+                parser = TableParser::default();
+                None
+            }
+        }))
+}
+
+/// The number of new xruns per pw-top update
+#[derive(Debug, Clone, Default)]
+pub struct XrunHistory {
+    /// the total of the last update, None before the first one
+    total: Option<u32>,
+    counts: VecDeque<u32>,
+}
+
+impl XrunHistory {
+    /// adds the xruns which happened since the previous update
+    pub fn push(&mut self, drivers: &[DriverStats]) {
+        let total: u32 = drivers.iter().map(|d| d.errors).sum();
+        // counters go down if a node disappeared, then there is nothing to compare to
+        let new = self.total.map_or(0, |previous| total.saturating_sub(previous));
+        self.total = Some(total);
+        self.counts.push_back(new);
+        if self.counts.len() > HISTORY_LEN {
+            self.counts.pop_front();
+        }
+    }
+
+    /// forgets the history, e.g. after a settings change
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+
+    /// @returns the new xruns of each update, the oldest first
+    pub fn counts(&self) -> &VecDeque<u32> {
+        &self.counts
+    }
+
+    /// @returns all xruns since the history was cleared
+    pub fn sum(&self) -> u32 {
+        self.counts.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static BATCH: &str = include_str!("../tests/fixtures/pw-top/batch.txt");
+
+    fn tables() -> Vec<Vec<DriverStats>> {
+        let mut parser = TableParser::default();
+        BATCH.lines().filter_map(|l| parser.push(l)).collect()
+    }
+
+    #[test]
+    fn parses_drivers_with_follower_errors() {
+        let tables = tables();
+        assert_eq!(tables.len(), 2);
+        let scarlett = &tables[0][2];
+        assert_eq!(scarlett, &DriverStats {
+            id: 46,
+            name: "alsa_output.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo".to_string(),
+            state: 'R',
+            quantum: Some(256),
+            rate: Some(48000),
+            busy: Some(0.0),
+            errors: 3,
+        });
+        assert!(!tables[0][0].is_running());
+        assert_eq!(tables[0][0].quantum, None);
+        assert_eq!(tables[0][0].busy, None);
+        assert_eq!(tables[0][3].name, "alsa_input.usb-Focusrite_Scarlett_2i2_USB-00.analog-stereo");
+    }
+
+    #[test]
+    fn counts_new_xruns() {
+        let mut history = XrunHistory::default();
+        for table in tables() {
+            history.push(&table);
+        }
+        assert_eq!(history.counts(), &VecDeque::from([0, 3]));
+        assert_eq!(history.sum(), 3);
+        history.clear();
+        history.push(&tables()[1]);
+        assert_eq!(history.sum(), 0);
+    }
+}
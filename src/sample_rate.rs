@@ -1,5 +1,7 @@
 
-use std::process::{Command, Stdio};
+use crate::error::Result;
+use crate::hardware::{self, HardwareLimits, LimitError};
+use crate::settings_backend::{SettingsBackend, FORCE_RATE_KEY, RATE_KEY, ALLOWED_RATES_KEY};
 
 pub fn get_available_sample_rates() -> Vec<u32> {
     vec![
@@ -13,47 +15,62 @@ pub fn get_available_sample_rates() -> Vec<u32> {
     ]
 }
 
-pub fn get_current_sample_rate() -> Option<u32> {
-    // fetch the current sample rate by terminal command
-    let cmd_str = "pw-metadata -n settings 0 clock.force-rate";
+/// @returns the reset and the given sample rates (e.g. the favourites) all active devices can run at.
+/// rates allowed in the pipewire settings are offered even if they are not given
+pub fn get_supported_sample_rates(limits: &HardwareLimits, rates: &[u32]) -> Vec<u32> {
+    let mut rates: Vec<u32> = std::iter::once(0).chain(rates.iter().copied()).collect();
+    for rate in &limits.allowed_rates {
+        if !rates.contains(rate) {
+            rates.push(*rate);
+        }
+    }
+    rates.retain(|rate| limits.supports_rate(*rate));
+    rates.sort_unstable();
+    rates.dedup();
+    rates
+}
 
-    let output = Command::new("sh")
-        .arg("-c")
-        .stdout(Stdio::piped())
-        .arg(cmd_str)
-        .output()
-        .unwrap();
+/// parses a typed sample rate and checks it against the limits
+pub fn parse_sample_rate(text: &str, limits: &HardwareLimits) -> std::result::Result<u32, LimitError> {
+    let rate = hardware::parse_value(text)?;
+    limits.check_rate(rate)?;
+    Ok(rate)
+}
 
-    // the response lookse something like this
-    /*
-    * Found "settings" metadata 31
-    * update: id:0 key:'clock.force-rate' value:'48000' type:''
-    */
-    let cmd_return_str = String::from_utf8(output.stdout).unwrap();
-    // first remove everything until "value:'"
-    let sub1 = &cmd_return_str[cmd_return_str.find("value:'").unwrap_or(0)..];
-    // now remove the "value:'" itself
-    let sub2 = &sub1["value:'".len()..];
-    // lastly, remove everything after the (now) first "'",
-    // as that concludes the actual value
-    let sub3 = &sub2[0..sub2.find("'").unwrap_or(sub2.len())];
+/// @returns the forced sample rate, None if none is forced
+pub fn get_current_sample_rate(backend: &dyn SettingsBackend) -> Result<Option<u32>> {
+    // a value pipewire can't make sense of either is treated as not forced
+    Ok(backend.get(FORCE_RATE_KEY)?.and_then(|value| value.parse().ok()))
+}
 
-    // turn it into the option for the combo box
-    sub3.parse().ok()
+/// @returns the sample rate pipewire uses when no sample rate is forced
+pub fn get_default_sample_rate(backend: &dyn SettingsBackend) -> Result<Option<u32>> {
+    Ok(backend.get(RATE_KEY)?.and_then(|value| value.parse().ok()))
 }
 
-pub fn set_sample_rate(rate: u32) {
+pub fn set_sample_rate(backend: &dyn SettingsBackend, rate: u32) -> Result<()> {
+    if rate == 0 {
+        backend.reset(FORCE_RATE_KEY)
+    } else {
+        backend.set(FORCE_RATE_KEY, &rate.to_string())
+    }
+}
 
-                // actually execute the change
-                let cmd = format!("pw-metadata -n settings 0 clock.force-rate {}", rate);
+/// @returns clock.allowed-rates, or None if it isn't set
+pub fn get_allowed_sample_rates(backend: &dyn SettingsBackend) -> Result<Option<Vec<u32>>> {
+    Ok(backend.get(ALLOWED_RATES_KEY)?.map(|value| hardware::parse_rate_list(&value)))
+}
 
-                let result = Command::new("sh")
-                    .arg("-c")
-                    .arg(cmd)
-                    .output();
-                match result {
-                    Ok(_) => println!("sample rate was set successfully!"),
-                    Err(e) => println!("error setting sample rate: {e}"),
-                }
+/// sets the rates the graph may switch to. an empty list acts as a reset
+pub fn set_allowed_sample_rates(backend: &dyn SettingsBackend, rates: &[u32]) -> Result<()> {
+    if rates.is_empty() {
+        return backend.reset(ALLOWED_RATES_KEY);
+    }
+    backend.set(ALLOWED_RATES_KEY, &format_rate_list(rates))
 }
 
+/// formats rates the way pipewire expects lists, e.g. "[ 44100 48000 ]"
+pub fn format_rate_list(rates: &[u32]) -> String {
+    let rates: Vec<String> = rates.iter().map(|r| r.to_string()).collect();
+    format!("[ {} ]", rates.join(" "))
+}
@@ -1,182 +1,94 @@
-
 // purpose here is to implement serde serializers for iced theme.
 // iced theme however is an enum, using palette as a potential custom entry
 // this in turn also uses color
 // all in all, there is a lot to work around to get this to serialize
 // see https://serde.rs/remote-derive.html
-// TODO: the enum{Custom(Arc<Custom>)} makes all of this very tricky. therefore skip it for now
-// for this to work at all, remember to add feature "rc" to serde in the Cargo.toml
-use std::sync::Arc;
-use serde::{Serialize, Deserialize};
-use iced::Theme;
-use iced::theme::Custom;
+// custom themes are stored as name and palette, e.g.
 /*
-use iced::Color;
-use iced::theme::palette;
-use iced::theme::palette::{Pair, Primary, Secondary, Background, Palette, Extended, Success, Danger };
+* "theme": { "Custom": { "name": "Studio Dark", "palette": { "background": "#1e1e2e", "text": "#cdd6f4",
+*     "primary": "#89b4fa", "success": "#a6e3a1", "danger": "#f38ba8" } } }
+*/
+// the extended palette is generated from that again when loading, see Custom::new
+use std::sync::Arc;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use iced::{Color, Theme};
+use iced::theme::{Custom, Palette};
 
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Color")]
-pub struct ColorDef{
-    /// Red component, 0.0 - 1.0
-    pub r: f32,
-    /// Green component, 0.0 - 1.0
-    pub g: f32,
-    /// Blue component, 0.0 - 1.0
-    pub b: f32,
-    /// Transparency, 0.0 - 1.0
-    pub a: f32,
+/// @returns color as "#rrggbb", or "#rrggbbaa" if it is translucent
+pub fn color_to_hex(color: Color) -> String {
+    let [r, g, b, a] = color.into_rgba8();
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+/// a color as hex string, see color_to_hex
+mod hex_color {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&color_to_hex(*color))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Color::parse(&hex).ok_or(serde::de::Error::custom(format!("'{}' is not a color like #1e1e2e", hex)))
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "Palette")]
 pub struct PaletteDef {
     /// The background [`Color`] of the [`Palette`].
-    #[serde(with = "ColorDef")]
+    #[serde(with = "hex_color")]
     pub background: Color,
     /// The text [`Color`] of the [`Palette`].
-    #[serde(with = "ColorDef")]
+    #[serde(with = "hex_color")]
     pub text: Color,
     /// The primary [`Color`] of the [`Palette`].
-    #[serde(with = "ColorDef")]
+    #[serde(with = "hex_color")]
     pub primary: Color,
     /// The success [`Color`] of the [`Palette`].
-    #[serde(with = "ColorDef")]
+    #[serde(with = "hex_color")]
     pub success: Color,
     /// The danger [`Color`] of the [`Palette`].
-    #[serde(with = "ColorDef")]
+    #[serde(with = "hex_color")]
     pub danger: Color,
 }
 
-/// A pair of background and text colors.
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Pair")]
-pub struct PairDef {
-    /// The background color.
-    #[serde(with = "ColorDef")]
-    pub color: Color,
-
-    /// The text color.
-    ///
-    /// It's guaranteed to be readable on top of the background [`color`].
-    ///
-    /// [`color`]: Self::color
-    #[serde(with = "ColorDef")]
-    pub text: Color,
-}
-
-/// A set of background colors.
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Background")]
-pub struct BackgroundDef {
-    /// The base background color.
-    #[serde(with = "PairDef")]
-    pub base: Pair,
-    /// A weaker version of the base background color.
-    #[serde(with = "PairDef")]
-    pub weak: Pair,
-    /// A stronger version of the base background color.
-    #[serde(with = "PairDef")]
-    pub strong: Pair,
-}
-
-/// A set of primary colors.
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Primary")]
-pub struct PrimaryDef {
-    /// The base primary color.
-    #[serde(with = "PairDef")]
-    pub base: Pair,
-    /// A weaker version of the base primary color.
-    #[serde(with = "PairDef")]
-    pub weak: Pair,
-    /// A stronger version of the base primary color.
-    #[serde(with = "PairDef")]
-    pub strong: Pair,
-}
-
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Secondary")]
-pub struct SecondaryDef {
-    /// The base secondary color.
-    #[serde(with = "PairDef")]
-    pub base: Pair,
-    /// A weaker version of the base secondary color.
-    #[serde(with = "PairDef")]
-    pub weak: Pair,
-    /// A stronger version of the base secondary color.
-    #[serde(with = "PairDef")]
-    pub strong: Pair,
+/// What is stored of a [`Custom`] theme. everything else is generated from the palette
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomTheme {
+    pub name: String,
+    #[serde(with = "PaletteDef")]
+    pub palette: Palette,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Success")]
-pub struct SuccessDef {
-    /// The base success color.
-    #[serde(with = "PairDef")]
-    pub base: Pair,
-    /// A weaker version of the base success color.
-    #[serde(with = "PairDef")]
-    pub weak: Pair,
-    /// A stronger version of the base success color.
-    #[serde(with = "PairDef")]
-    pub strong: Pair,
-}
+impl CustomTheme {
+    pub fn from_theme(theme: &Theme) -> Self {
+        Self { name: theme.to_string(), palette: theme.palette() }
+    }
 
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Danger")]
-pub struct DangerDef {
-    /// The base danger color.
-    #[serde(with = "PairDef")]
-    pub base: Pair,
-    /// A weaker version of the base danger color.
-    #[serde(with = "PairDef")]
-    pub weak: Pair,
-    /// A stronger version of the base danger color.
-    #[serde(with = "PairDef")]
-    pub strong: Pair,
+    pub fn to_theme(&self) -> Theme {
+        Theme::Custom(Arc::new(Custom::new(self.name.clone(), self.palette)))
+    }
 }
 
-/// An extended set of colors generated from a [`Palette`].
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Extended")]
-pub struct ExtendedDef {
-    /// The set of background colors.
-    #[serde(with = "BackgroundDef")]
-    pub background: Background,
-    /// The set of primary colors.
-    #[serde(with = "PrimaryDef")]
-    pub primary: Primary,
-    /// The set of secondary colors.
-    #[serde(with = "SecondaryDef")]
-    pub secondary: Secondary,
-    /// The set of success colors.
-    #[serde(with = "SuccessDef")]
-    pub success: Success,
-    /// The set of danger colors.
-    #[serde(with = "DangerDef")]
-    pub danger: Danger,
-    /// Whether the palette is dark or not.
-    pub is_dark: bool,
-}
+/// Theme::Custom as CustomTheme
+mod custom_theme {
+    use super::*;
 
-/// A [`Theme`] with a customized [`Palette`].
-#[derive(Serialize, Deserialize)]
-#[serde(remote = "Custom")]
-pub struct CustomDef {
-    name: String,
-    #[serde(with = "PaletteDef")]
-    palette: Palette,
-    #[serde(with = "ExtendedDef")]
-    extended: palette::Extended,
-}
-
-impl From<CustomDef> for Custom {
-    fn from(custom: CustomDef) -> Custom {
-        Custom::new(custom.name, customm.palette);
+    pub fn serialize<S: Serializer>(custom: &Arc<Custom>, serializer: S) -> Result<S::Ok, S::Error> {
+        CustomTheme::from_theme(&Theme::Custom(custom.clone())).serialize(serializer)
     }
-}*/
 
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Arc<Custom>, D::Error> {
+        let custom = CustomTheme::deserialize(deserializer)?;
+        Ok(Arc::new(Custom::new(custom.name, custom.palette)))
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "Theme")]
@@ -225,10 +137,40 @@ pub enum ThemeDef{
     Oxocarbon,
     /// The built-in Ferra variant:
     Ferra,
-    // A [`Theme`] that uses a [`Custom`] palette.
-    // #[serde(with = "CustomDef")]
-    // TODO: we have all the custom values defined for serde,
-    // but how can we use it in the Arc in an Enum?
-    #[serde(skip)]
+    /// A [`Theme`] that uses a [`Custom`] palette.
+    #[serde(with = "custom_theme")]
     Custom(Arc<Custom>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Config {
+        #[serde(with = "ThemeDef")]
+        theme: Theme,
+    }
+
+    #[test]
+    fn custom_theme_survives_a_round_trip() {
+        let custom = CustomTheme {
+            name: "Studio Dark".to_string(),
+            // hex only has 8 bits per channel
+            palette: Palette {
+                background: Color::from_rgb8(0x1e, 0x1e, 0x2e),
+                text: Color::from_rgb8(0xcd, 0xd6, 0xf4),
+                primary: Color::from_rgb8(0x89, 0xb4, 0xfa),
+                success: Color::from_rgb8(0xa6, 0xe3, 0xa1),
+                danger: Color::from_rgba8(0xf3, 0x8b, 0xa8, 128.0 / 255.0),
+            },
+        };
+        let json = serde_json::to_string(&Config { theme: custom.to_theme() }).unwrap();
+        assert!(json.contains(r##""Custom":{"name":"Studio Dark","palette":{"background":"#1e1e2e""##));
+
+        let loaded: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(CustomTheme::from_theme(&loaded.theme), custom);
+        let builtin: Config = serde_json::from_str(r#"{"theme":"Nord"}"#).unwrap();
+        assert_eq!(builtin.theme, Theme::Nord);
+    }
+}
@@ -0,0 +1,138 @@
+
+// abstraction over the pipewire "settings" metadata.
+// the GUI only ever talks to the SettingsBackend trait, so that the actual
+// pw-metadata calls can be swapped out (e.g. for an in-memory fake in tests)
+
+use std::process::Command;
+use iced::futures::{Stream, StreamExt};
+
+use crate::clients::{self, AudioClient};
+use crate::error::{LateError, Result};
+use crate::process_stream::{self, Output};
+use crate::pw_metadata::{self, MetadataLine};
+
+/// the metadata object pipewire stores its clock settings in
+pub static SETTINGS_METADATA: &str = "settings";
+/// the key forcing a buffer size (quantum). 0 or unset means not forced
+pub static FORCE_QUANTUM_KEY: &str = "clock.force-quantum";
+/// the key forcing a sample rate. 0 or unset means not forced
+pub static FORCE_RATE_KEY: &str = "clock.force-rate";
+/// the quantum pipewire uses when nothing is forced
+pub static QUANTUM_KEY: &str = "clock.quantum";
+/// the sample rate pipewire uses when nothing is forced
+pub static RATE_KEY: &str = "clock.rate";
+/// the smallest quantum clients may ask for
+pub static MIN_QUANTUM_KEY: &str = "clock.min-quantum";
+/// the largest quantum clients may ask for
+pub static MAX_QUANTUM_KEY: &str = "clock.max-quantum";
+/// the sample rates the graph may switch to, e.g. "[ 44100 48000 ]"
+pub static ALLOWED_RATES_KEY: &str = "clock.allowed-rates";
+
+/// Read and write access to keys of the pipewire `settings` metadata (subject 0),
+/// as well as the clients that are affected by changing them
+pub trait SettingsBackend {
+    /// @returns the value stored for key, or None if the key is not set
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    /// stores value for key
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+    /// removes key, so that pipewire falls back to its default
+    fn reset(&self, key: &str) -> Result<()>;
+    /// @returns the audio clients that are currently active
+    fn active_clients(&self) -> Result<Vec<AudioClient>>;
+}
+
+/// Backend that calls the pw-metadata binary directly (i.e. not through a shell)
+#[derive(Default)]
+pub struct PwMetadataBackend;
+
+impl PwMetadataBackend {
+    /// runs pw-metadata on the settings metadata with the given arguments
+    /// @returns stdout of the process
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("pw-metadata")
+            .arg("-n")
+            .arg(SETTINGS_METADATA)
+            .args(args)
+            .output()
+            .map_err(|source| LateError::Spawn { program: "pw-metadata".to_string(), source })?;
+
+        if !output.status.success() {
+            return Err(LateError::Exit {
+                program: "pw-metadata".to_string(),
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl SettingsBackend for PwMetadataBackend {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let stdout = self.run(&["0", key])?;
+        let entries = pw_metadata::parse(&stdout)
+            .map_err(|e| LateError::Parse { what: "the output of pw-metadata".to_string(), message: e.to_string() })?;
+        Ok(pw_metadata::find(&entries, 0, key).map(|e| e.value.clone()))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.run(&["0", key, value]).map(|_| ())
+    }
+
+    fn reset(&self, key: &str) -> Result<()> {
+        self.run(&["-d", "0", key]).map(|_| ())
+    }
+
+    fn active_clients(&self) -> Result<Vec<AudioClient>> {
+        clients::query()
+    }
+}
+
+/// @returns a stream of every change to the settings metadata, no matter who made it, or why watching failed.
+/// pw-metadata reports the current values first, so no change gets lost in between,
+/// also when it has to be started again
+pub fn watch_settings() -> impl Stream<Item = Result<MetadataLine>> {
+    process_stream::lines("pw-metadata", &["-m", "-n", "settings"])
+        .filter_map(|output| async move {
+            match output {
+                // lines which can't be parsed are reported, the next update will correct the state
+                Output::Line { nr, line } => pw_metadata::parse_line(&line, nr)
+                    .map_err(|e| LateError::Parse {
+                        what: "the output of pw-metadata -m".to_string(),
+                        message: e.to_string(),
+                    })
+                    .transpose(),
+                Output::Stopped(e) => Some(Err(e)),
+            }
+        })
+}
+
+/// In-memory backend, so that the GUI state can be tested without a running pipewire
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    // shared, so a test can keep a clone around to inspect what the GUI wrote
+    values: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, String>>>,
+    pub clients: std::sync::Arc<std::sync::Mutex<Vec<AudioClient>>>,
+}
+
+#[cfg(test)]
+impl SettingsBackend for MemoryBackend {
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.values.lock().unwrap().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn reset(&self, key: &str) -> Result<()> {
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn active_clients(&self) -> Result<Vec<AudioClient>> {
+        Ok(self.clients.lock().unwrap().clone())
+    }
+}
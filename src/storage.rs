@@ -0,0 +1,205 @@
+// where late keeps its files, and how it writes them.
+// config and profiles live in $XDG_CONFIG_HOME/late (~/.config/late if it is unset),
+// or in the directory given with --config-dir.
+// files are written to a temporary file, which is then renamed over the old one, so a
+// crash mid-write leaves the old file intact. reads and writes take an advisory lock on
+// late.lock, so that two instances of late don't write at the same time. changes which depend
+// on what is in a file (e.g. adding a profile) hold the lock from reading to writing it.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{self, LateError};
+
+pub static CONFIG_NAME: &str = "late_config.json";
+pub static PROFILES_NAME: &str = "late_profiles.json";
+static APP_DIR: &str = "late";
+static LOCK_NAME: &str = "late.lock";
+
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// makes late keep config and profiles in dir. only the first call has an effect
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR_OVERRIDE.set(dir);
+}
+
+/// @returns the dir given with --config-dir, if any
+pub fn config_dir_override() -> Option<&'static Path> {
+    CONFIG_DIR_OVERRIDE.get().map(PathBuf::as_path)
+}
+
+/// @returns $XDG_CONFIG_HOME, or ~/.config if it is unset.
+/// relative paths are ignored, as the XDG base directory spec demands
+pub fn xdg_config_home() -> error::Result<PathBuf> {
+    resolve_config_home(std::env::var_os("XDG_CONFIG_HOME"), home::home_dir())
+}
+
+fn resolve_config_home(xdg_config_home: Option<OsString>, home: Option<PathBuf>) -> error::Result<PathBuf> {
+    match xdg_config_home.map(PathBuf::from).filter(|dir| dir.is_absolute()) {
+        Some(dir) => Ok(dir),
+        None => home.map(|home| home.join(".config")).ok_or(LateError::NoHome),
+    }
+}
+
+/// @returns the directory late keeps config and profiles in
+pub fn config_dir() -> error::Result<PathBuf> {
+    match CONFIG_DIR_OVERRIDE.get() {
+        Some(dir) => Ok(dir.clone()),
+        None => Ok(xdg_config_home()?.join(APP_DIR)),
+    }
+}
+
+/// locks late.lock in dir, shared for reading or exclusive for writing.
+/// the lock is released when the returned file is closed
+fn lock(dir: &Path, exclusive: bool) -> io::Result<File> {
+    let path = dir.join(LOCK_NAME);
+    let file = if exclusive {
+        OpenOptions::new().create(true).truncate(false).write(true).open(path)?
+    } else {
+        // reading must not need write access, the dir may be read-only
+        File::open(path)?
+    };
+    if exclusive {
+        file.lock()?;
+    } else {
+        file.lock_shared()?;
+    }
+    Ok(file)
+}
+
+/// reads the file name from dir. a file which doesn't exist yet reads as empty
+pub fn read_in(dir: &Path, name: &str) -> error::Result<String> {
+    let path = dir.join(name);
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    let _lock = match lock(dir, false) {
+        Ok(lock) => Some(lock),
+        // nothing was written by a version of late that locks, so there is nobody to wait for
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(LateError::io(dir.join(LOCK_NAME), e)),
+    };
+    fs::read_to_string(&path).map_err(|e| LateError::io(&path, e))
+}
+
+/// replaces the file name in dir with contents, creating dir if needed
+#[cfg(test)]
+pub fn write_in(dir: &Path, name: &str, contents: &str) -> error::Result<()> {
+    update_in(dir, name, |_| Ok(contents.to_string()))
+}
+
+/// replaces the file name in dir with what change makes of its contents, holding the lock in between,
+/// so that no other instance of late writes the file meanwhile.
+/// a file which doesn't exist yet reads as empty. nothing is written if change fails
+pub fn update_in(dir: &Path, name: &str, change: impl FnOnce(&str) -> error::Result<String>) -> error::Result<()> {
+    fs::create_dir_all(dir).map_err(|e| LateError::io(dir, e))?;
+    let _lock = lock(dir, true).map_err(|e| LateError::io(dir.join(LOCK_NAME), e))?;
+
+    let path = dir.join(name);
+    // read_in would wait for the lock held here
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(LateError::io(&path, e)),
+    };
+    replace_file(&path, &change(&contents)?).map_err(|e| LateError::io(&path, e))
+}
+
+/// replaces the file at path with contents by renaming a temporary file over it.
+/// the directory of path has to exist
+pub fn replace_file(path: &Path, contents: &str) -> io::Result<()> {
+    // next to the file, since a rename is only atomic within one file system
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let written = File::create(&temp)
+        .and_then(|mut f| {
+            f.write_all(contents.as_bytes())?;
+            // the data has to be on disk before the rename is
+            f.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// copies path next to itself, e.g. to late_profiles.json.v0.1700000000.bak.
+/// if there is an identical copy for the same reason already, that one is returned instead,
+/// so a broken file read over and over (e.g. by the tray) is only copied once
+pub fn backup(path: &Path, reason: &str) -> io::Result<PathBuf> {
+    let contents = fs::read(path)?;
+    let prefix = format!("{}.{}.", path.file_name().unwrap_or_default().to_string_lossy(), reason);
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let existing = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(".bak")
+        })
+        .find(|entry| fs::read(entry.path()).is_ok_and(|copy| copy == contents));
+    if let Some(existing) = existing {
+        return Ok(existing.path());
+    }
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let backup = path.with_file_name(format!("{}{}.bak", prefix, secs));
+    fs::write(&backup, contents)?;
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_absolute_xdg_config_home() {
+        let home = Some(PathBuf::from("/home/tim"));
+        assert_eq!(resolve_config_home(Some("/tmp/config".into()), home.clone()).unwrap(),
+            PathBuf::from("/tmp/config"));
+        assert_eq!(resolve_config_home(Some("config".into()), home.clone()).unwrap(),
+            PathBuf::from("/home/tim/.config"));
+        assert_eq!(resolve_config_home(None, home).unwrap(), PathBuf::from("/home/tim/.config"));
+        assert!(resolve_config_home(None, None).is_err());
+    }
+
+    #[test]
+    fn writes_through_a_temporary_file() {
+        let dir = std::env::temp_dir().join(format!("late-storage-{}", std::process::id()));
+        let nested = dir.join("not/there/yet");
+        assert_eq!(read_in(&nested, PROFILES_NAME).unwrap(), "");
+
+        write_in(&nested, PROFILES_NAME, "first").unwrap();
+        write_in(&nested, PROFILES_NAME, "second").unwrap();
+        assert_eq!(read_in(&nested, PROFILES_NAME).unwrap(), "second");
+        let mut names: Vec<String> = fs::read_dir(&nested).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![LOCK_NAME, PROFILES_NAME]);
+
+        update_in(&nested, PROFILES_NAME, |contents| Ok(format!("{contents} third"))).unwrap();
+        assert!(update_in(&nested, PROFILES_NAME, |_| Err(LateError::NoHome)).is_err());
+        assert_eq!(read_in(&nested, PROFILES_NAME).unwrap(), "second third");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_without_creating_the_lock() {
+        // e.g. a dir written by an older version, or one late can't write to
+        let dir = std::env::temp_dir().join(format!("late-storage-read-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(CONFIG_NAME), "{}").unwrap();
+
+        assert_eq!(read_in(&dir, CONFIG_NAME).unwrap(), "{}");
+        assert!(!dir.join(LOCK_NAME).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+// tray icon, so late can keep running without its window.
+// implements a StatusNotifierItem (the tray protocol of KDE, GNOME with AppIndicator
+// and most other desktops) and its dbusmenu on the session bus:
+// https://www.freedesktop.org/wiki/Specifications/StatusNotifierItem/
+// the tray runs on its own thread. it shows the TrayStatus the GUI keeps up to date, so it doesn't
+// ask pipewire itself, and passes clicks in the menu back to the GUI as TrayCommands, and its errors
+// as messages to show.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use iced::futures::channel::mpsc;
+use iced::futures::{executor, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use zbus::blocking::{connection, Connection};
+use zbus::object_server::SignalContext;
+use zbus::zvariant::{OwnedValue, Type, Value};
+
+static ITEM_PATH: &str = "/StatusNotifierItem";
+static MENU_PATH: &str = "/MenuBar";
+static WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+static WATCHER_PATH: &str = "/StatusNotifierWatcher";
+
+const OPEN_ID: i32 = 1;
+const QUIT_ID: i32 = 2;
+/// the profiles get the ids from here on, in the order they are saved
+const FIRST_PROFILE_ID: i32 = 100;
+/// how often the status the GUI keeps is compared with the one shown
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// (icon name, icon pixmaps as (width, height, ARGB data), title, description)
+type ToolTip = (String, Vec<(i32, i32, Vec<u8>)>, String, String);
+
+/// What the user chose in the tray, or what went wrong showing it
+type TrayEvent = Result<TrayCommand, String>;
+
+/// What the user chose in the tray
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrayCommand {
+    ShowWindow,
+    ApplyProfile(String),
+    Quit,
+}
+
+/// What the tray shows, kept up to date by the GUI
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrayStatus {
+    /// the values the graph runs at, as far as they are known
+    pub quantum: Option<u32>,
+    pub rate: Option<u32>,
+    pub profiles: Vec<String>,
+    /// the profile matching the forced values, if any
+    pub profile: Option<String>,
+}
+
+impl TrayStatus {
+    /// e.g. "256 / 48000 Hz (5.3 ms)"
+    pub fn summary(&self) -> String {
+        match (self.quantum, self.rate) {
+            (Some(quantum), Some(rate)) if rate != 0 =>
+                format!("{} / {} Hz ({:.1} ms)", quantum, rate, quantum as f32 * 1000.0 / rate as f32),
+            (quantum, rate) => format!("{} / {} Hz",
+                quantum.map_or("?".to_string(), |q| q.to_string()),
+                rate.map_or("?".to_string(), |r| r.to_string())),
+        }
+    }
+}
+
+/// One entry of the dbusmenu layout, (ia{sv}av) on the bus
+#[derive(Debug, PartialEq, Serialize, Deserialize, Type, Value, OwnedValue)]
+pub struct MenuItem {
+    id: i32,
+    properties: HashMap<String, OwnedValue>,
+    /// more MenuItems, each wrapped in a variant
+    children: Vec<OwnedValue>,
+}
+
+impl MenuItem {
+    fn new(id: i32, properties: Vec<(&str, OwnedValue)>) -> Self {
+        Self {
+            id,
+            properties: properties.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            children: Vec::new(),
+        }
+    }
+
+    fn label(id: i32, label: &str) -> Self {
+        Self::new(id, vec![("label", text(label))])
+    }
+
+    fn separator(id: i32) -> Self {
+        Self::new(id, vec![("type", text("separator"))])
+    }
+}
+
+fn text(s: &str) -> OwnedValue {
+    Value::from(s).try_into().expect("strings are always owned values")
+}
+
+/// @returns the whole menu: open, the profiles to switch to and quit
+pub fn menu_layout(status: &TrayStatus) -> MenuItem {
+    let mut items = vec![
+        MenuItem::label(OPEN_ID, "Open Late"),
+        MenuItem::separator(3),
+    ];
+    for (i, name) in status.profiles.iter().enumerate() {
+        let mut item = MenuItem::label(FIRST_PROFILE_ID + i as i32, name);
+        item.properties.insert("toggle-type".to_string(), text("radio"));
+        let checked = status.profile.as_ref() == Some(name);
+        item.properties.insert("toggle-state".to_string(), OwnedValue::from(checked as i32));
+        items.push(item);
+    }
+    items.push(MenuItem::separator(4));
+    items.push(MenuItem::label(QUIT_ID, "Quit"));
+
+    let mut root = MenuItem::new(0, vec![("children-display", text("submenu"))]);
+    root.children = items.into_iter()
+        .map(|item| item.try_into().expect("menu items only contain owned values"))
+        .collect();
+    root
+}
+
+/// @returns what clicking the menu entry id means
+fn command_for(status: &TrayStatus, id: i32) -> Option<TrayCommand> {
+    match id {
+        OPEN_ID => Some(TrayCommand::ShowWindow),
+        QUIT_ID => Some(TrayCommand::Quit),
+        _ => {
+            let index = usize::try_from(id - FIRST_PROFILE_ID).ok()?;
+            status.profiles.get(index).cloned().map(TrayCommand::ApplyProfile)
+        }
+    }
+}
+
+struct StatusNotifierItem {
+    status: Arc<Mutex<TrayStatus>>,
+    commands: mpsc::UnboundedSender<TrayEvent>,
+}
+
+#[zbus::interface(name = "org.kde.StatusNotifierItem")]
+impl StatusNotifierItem {
+    #[zbus(property)]
+    fn category(&self) -> String {
+        "Hardware".to_string()
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> String {
+        "late".to_string()
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> String {
+        format!("Late - {}", self.status.lock().unwrap().summary())
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> String {
+        "Active".to_string()
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> String {
+        "audio-card".to_string()
+    }
+
+    #[zbus(property)]
+    fn tool_tip(&self) -> ToolTip {
+        let summary = self.status.lock().unwrap().summary();
+        ("audio-card".to_string(), Vec::new(), "Late".to_string(), summary)
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> zbus::zvariant::OwnedObjectPath {
+        zbus::zvariant::ObjectPath::from_static_str_unchecked(MENU_PATH).into()
+    }
+
+    fn activate(&self, _x: i32, _y: i32) {
+        let _ = self.commands.unbounded_send(Ok(TrayCommand::ShowWindow));
+    }
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    fn context_menu(&self, _x: i32, _y: i32) {}
+
+    fn scroll(&self, _delta: i32, _orientation: String) {}
+
+    #[zbus(signal)]
+    async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+}
+
+struct DbusMenu {
+    status: Arc<Mutex<TrayStatus>>,
+    commands: mpsc::UnboundedSender<TrayEvent>,
+    /// increased whenever the profiles change
+    revision: Arc<Mutex<u32>>,
+}
+
+#[zbus::interface(name = "com.canonical.dbusmenu")]
+impl DbusMenu {
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> String {
+        "ltr".to_string()
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// the menu is small, so the whole layout is returned regardless of parent and depth
+    fn get_layout(&self, _parent_id: i32, _recursion_depth: i32, _property_names: Vec<String>)
+        -> (u32, MenuItem) {
+        let layout = menu_layout(&self.status.lock().unwrap());
+        (*self.revision.lock().unwrap(), layout)
+    }
+
+    fn get_group_properties(&self, ids: Vec<i32>, _property_names: Vec<String>)
+        -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        let layout = menu_layout(&self.status.lock().unwrap());
+        layout.children.iter()
+            .filter_map(|child| MenuItem::try_from(child.try_clone().ok()?).ok())
+            .filter(|item| ids.is_empty() || ids.contains(&item.id))
+            .map(|item| (item.id, item.properties))
+            .collect()
+    }
+
+    fn get_property(&self, id: i32, name: String) -> zbus::fdo::Result<OwnedValue> {
+        self.get_group_properties(vec![id], Vec::new())
+            .into_iter()
+            .find_map(|(_, mut properties)| properties.remove(&name))
+            .ok_or(zbus::fdo::Error::InvalidArgs(format!("no property {name} on item {id}")))
+    }
+
+    fn event(&self, id: i32, event_id: String, _data: OwnedValue, _timestamp: u32) {
+        if event_id != "clicked" {
+            return;
+        }
+        if let Some(command) = command_for(&self.status.lock().unwrap(), id) {
+            let _ = self.commands.unbounded_send(Ok(command));
+        }
+    }
+
+    fn event_group(&self, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        for (id, event_id, data, timestamp) in events {
+            self.event(id, event_id, data, timestamp);
+        }
+        Vec::new()
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (Vec::new(), Vec::new())
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(ctxt: &SignalContext<'_>, revision: u32, parent: i32) -> zbus::Result<()>;
+}
+
+/// shows the tray icon with status as long as the stream is alive.
+/// @returns a stream of what the user chose in the tray menu, or of why the tray can't be shown
+pub fn watch(status: Arc<Mutex<TrayStatus>>) -> impl Stream<Item = TrayEvent> {
+    iced::stream::channel(10, |mut output| async move {
+        let (sender, mut receiver) = mpsc::unbounded();
+        thread::spawn(move || {
+            if let Err(e) = serve(&status, &sender) {
+                let _ = sender.unbounded_send(Err(format!("Could not show the tray icon: {e}")));
+            }
+        });
+
+        while let Some(command) = receiver.next().await {
+            if output.send(command).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// puts the tray icon and its menu on the bus builder connects to, showing status.
+/// clicks are sent to commands
+/// @returns the connection, the icon is available as long as it is kept
+fn register(builder: connection::Builder<'_>, name: &str, status: Arc<Mutex<TrayStatus>>, revision: Arc<Mutex<u32>>,
+    commands: &mpsc::UnboundedSender<TrayEvent>) -> zbus::Result<Connection> {
+    let item = StatusNotifierItem { status: status.clone(), commands: commands.clone() };
+    let menu = DbusMenu { status, commands: commands.clone(), revision };
+    builder
+        .name(name)?
+        .serve_at(ITEM_PATH, item)?
+        .serve_at(MENU_PATH, menu)?
+        .build()
+}
+
+/// registers the tray icon and shows what is in status until nobody listens anymore
+fn serve(status: &Mutex<TrayStatus>, commands: &mpsc::UnboundedSender<TrayEvent>) -> zbus::Result<()> {
+    // a copy of its own, so that the tray only shows what it has announced
+    let shown = Arc::new(Mutex::new(status.lock().unwrap().clone()));
+    let revision = Arc::new(Mutex::new(0));
+    let name = format!("org.kde.StatusNotifierItem-{}-1", std::process::id());
+    let connection = register(connection::Builder::session()?, &name, shown.clone(), revision.clone(), commands)?;
+
+    // without a watcher (i.e. no tray on the desktop) the item is still on the bus,
+    // a tray started later picks it up
+    if let Err(e) = connection.call_method(Some(WATCHER_NAME), WATCHER_PATH, Some(WATCHER_NAME),
+        "RegisterStatusNotifierItem", &(name.as_str())) {
+        let _ = commands.unbounded_send(Err(format!("Could not register the tray icon, is there a tray? {e}")));
+    }
+
+    let item = connection.object_server().interface::<_, StatusNotifierItem>(ITEM_PATH)?;
+    let menu = connection.object_server().interface::<_, DbusMenu>(MENU_PATH)?;
+    while !commands.is_closed() {
+        thread::sleep(REFRESH_INTERVAL);
+        let current = status.lock().unwrap().clone();
+        let previous = std::mem::replace(&mut *shown.lock().unwrap(), current.clone());
+        if previous.quantum != current.quantum || previous.rate != current.rate {
+            executor::block_on(StatusNotifierItem::new_title(item.signal_context()))?;
+            executor::block_on(StatusNotifierItem::new_tool_tip(item.signal_context()))?;
+        }
+        if previous.profiles != current.profiles || previous.profile != current.profile {
+            let revision = {
+                let mut revision = revision.lock().unwrap();
+                *revision += 1;
+                *revision
+            };
+            executor::block_on(DbusMenu::layout_updated(menu.signal_context(), revision, 0))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus_service::tests::PrivateBus;
+
+    fn status() -> TrayStatus {
+        TrayStatus {
+            quantum: Some(256),
+            rate: Some(48000),
+            profiles: vec!["Recording".to_string(), "Mixing".to_string()],
+            profile: Some("Mixing".to_string()),
+        }
+    }
+
+    #[test]
+    fn menu_lists_profiles_and_marks_current() {
+        let layout = menu_layout(&status());
+        let items: Vec<MenuItem> = layout.children.into_iter()
+            .map(|child| MenuItem::try_from(child).unwrap())
+            .collect();
+        let labels: Vec<Option<&str>> = items.iter()
+            .map(|item| item.properties.get("label").and_then(|l| l.downcast_ref::<&str>().ok()))
+            .collect();
+        assert_eq!(labels, vec![Some("Open Late"), None, Some("Recording"), Some("Mixing"), None, Some("Quit")]);
+        assert_eq!(items[3].properties["toggle-state"], OwnedValue::from(1));
+        assert_eq!(items[2].properties["toggle-state"], OwnedValue::from(0));
+    }
+
+    #[test]
+    fn menu_works_over_the_bus() {
+        let Some(bus) = PrivateBus::start() else {
+            println!("dbus-daemon not available, skipping");
+            return;
+        };
+        let (sender, receiver) = mpsc::unbounded();
+        let _tray = register(connection::Builder::address(bus.address.as_str()).unwrap(), "org.kde.StatusNotifierItem-1-1",
+            Arc::new(Mutex::new(status())), Arc::new(Mutex::new(7)), &sender).unwrap();
+        let client = connection::Builder::address(bus.address.as_str()).unwrap().build().unwrap();
+        let call = |method: &str, body: &(i32, String, OwnedValue, u32)| client
+            .call_method(Some("org.kde.StatusNotifierItem-1-1"), MENU_PATH, Some("com.canonical.dbusmenu"), method, body);
+
+        let (revision, layout): (u32, MenuItem) = client
+            .call_method(Some("org.kde.StatusNotifierItem-1-1"), MENU_PATH, Some("com.canonical.dbusmenu"),
+                "GetLayout", &(0, -1, Vec::<String>::new()))
+            .unwrap().body().deserialize().unwrap();
+        assert_eq!((revision, layout), (7, menu_layout(&status())));
+
+        let click = |id: i32| (id, "clicked".to_string(), OwnedValue::from(0), 0);
+        call("Event", &(FIRST_PROFILE_ID, "hovered".to_string(), OwnedValue::from(0), 0)).unwrap();
+        call("Event", &click(FIRST_PROFILE_ID)).unwrap();
+        call("Event", &click(QUIT_ID)).unwrap();
+        assert_eq!(executor::block_on_stream(receiver).take(2).collect::<Vec<_>>(), vec![
+            Ok(TrayCommand::ApplyProfile("Recording".to_string())),
+            Ok(TrayCommand::Quit),
+        ]);
+    }
+
+    #[test]
+    fn clicks_become_commands() {
+        let status = status();
+        assert_eq!(command_for(&status, OPEN_ID), Some(TrayCommand::ShowWindow));
+        assert_eq!(command_for(&status, FIRST_PROFILE_ID + 1), Some(TrayCommand::ApplyProfile("Mixing".to_string())));
+        assert_eq!(command_for(&status, FIRST_PROFILE_ID + 2), None);
+        assert_eq!(command_for(&status, 3), None);
+        assert_eq!(status.summary(), "256 / 48000 Hz (5.3 ms)");
+    }
+}
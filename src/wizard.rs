@@ -0,0 +1,160 @@
+// finds the lowest buffer size which runs without xruns.
+// starting at the running buffer size, each smaller preset is forced for a while
+// (the soak time) while pw-top counts the xruns. the first buffer size with xruns
+// ends the search, the one before it is the recommendation.
+
+use crate::buffer_size;
+use crate::hardware::HardwareLimits;
+
+/// xruns right after switching the buffer size are caused by the switch itself
+const SETTLE_SECS: u32 = 2;
+
+/// What to do after a tick of the wizard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WizardStep {
+    /// keep waiting
+    Wait,
+    /// force the next buffer size
+    Set(u32),
+    /// the search is over
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct StabilityWizard {
+    /// the buffer sizes to try, largest first
+    candidates: Vec<u32>,
+    step: usize,
+    soak_secs: u32,
+    elapsed: u32,
+    /// xruns of the current step
+    xruns: u32,
+    /// the smallest buffer size which ran without xruns so far
+    stable: Option<u32>,
+    done: bool,
+    /// the forced buffer size before the wizard started, restored afterwards
+    pub previous: Option<u32>,
+}
+
+impl StabilityWizard {
+    /// @returns None if there is nothing to try
+    pub fn new(candidates: Vec<u32>, soak_secs: u32, previous: Option<u32>) -> Option<Self> {
+        if candidates.is_empty() {
+            return None;
+        }
+        Some(Self {
+            candidates,
+            step: 0,
+            soak_secs: soak_secs.max(SETTLE_SECS + 1),
+            elapsed: 0,
+            xruns: 0,
+            stable: None,
+            done: false,
+            previous,
+        })
+    }
+
+    /// the buffer size being tried
+    pub fn current(&self) -> u32 {
+        self.candidates[self.step]
+    }
+
+    /// @returns the smallest buffer size that stayed clean, once done
+    pub fn recommendation(&self) -> Option<u32> {
+        self.stable
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn remaining_secs(&self) -> u32 {
+        self.soak_secs.saturating_sub(self.elapsed)
+    }
+
+    /// xruns of the current step so far
+    pub fn xruns(&self) -> u32 {
+        self.xruns
+    }
+
+    /// counts xruns reported by pw-top
+    pub fn add_xruns(&mut self, count: u32) {
+        if !self.done && self.elapsed >= SETTLE_SECS {
+            self.xruns += count;
+        }
+    }
+
+    /// advances the wizard by one second
+    pub fn tick(&mut self) -> WizardStep {
+        if self.done {
+            return WizardStep::Done;
+        }
+        self.elapsed += 1;
+        if self.xruns > 0 {
+            self.done = true;
+            return WizardStep::Done;
+        }
+        if self.elapsed < self.soak_secs {
+            return WizardStep::Wait;
+        }
+
+        self.stable = Some(self.current());
+        if self.step + 1 == self.candidates.len() {
+            self.done = true;
+            return WizardStep::Done;
+        }
+        self.step += 1;
+        self.elapsed = 0;
+        WizardStep::Set(self.current())
+    }
+}
+
+/// @returns the supported buffer sizes up to start (all if unknown), largest first
+pub fn candidates(limits: &HardwareLimits, start: Option<u32>) -> Vec<u32> {
+    let mut sizes: Vec<u32> = buffer_size::get_supported_buffer_sizes(limits, &buffer_size::get_available_buffer_sizes())
+        .into_iter()
+        .filter(|size| *size != 0 && start.is_none_or(|start| *size <= start))
+        .collect();
+    sizes.reverse();
+    sizes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn soak(wizard: &mut StabilityWizard) -> WizardStep {
+        loop {
+            match wizard.tick() {
+                WizardStep::Wait => {}
+                step => return step,
+            }
+        }
+    }
+
+    #[test]
+    fn recommends_last_clean_size() {
+        let mut wizard = StabilityWizard::new(vec![256, 128, 64], 5, Some(256)).unwrap();
+        assert_eq!(soak(&mut wizard), WizardStep::Set(128));
+
+        // xruns caused by switching don't count
+        wizard.add_xruns(4);
+        assert_eq!(wizard.tick(), WizardStep::Wait);
+        assert_eq!(wizard.tick(), WizardStep::Wait);
+        assert_eq!(soak(&mut wizard), WizardStep::Set(64));
+
+        wizard.tick();
+        wizard.tick();
+        wizard.add_xruns(1);
+        assert_eq!(wizard.tick(), WizardStep::Done);
+        assert!(wizard.is_done());
+        assert_eq!(wizard.recommendation(), Some(128));
+    }
+
+    #[test]
+    fn candidates_start_at_running_size() {
+        let limits = HardwareLimits { min_quantum: Some(128), ..Default::default() };
+        assert_eq!(candidates(&limits, Some(512)), vec![512, 256, 128]);
+        assert_eq!(candidates(&limits, None), vec![2048, 1024, 512, 256, 128]);
+    }
+}
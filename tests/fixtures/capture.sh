@@ -0,0 +1,88 @@
+#!/bin/sh
+# captures the pw-metadata fixtures from the running pipewire, see README.md.
+# the clock.* settings are changed to known values while capturing, so that the tests find what they
+# expect, and put back afterwards. remove_all.txt removes all keys of the settings metadata, restart
+# pipewire afterwards to get log.level, clock.rate and clock.quantum back.
+#
+# usage: tests/fixtures/capture.sh [--with-remove-all]
+
+set -eu
+cd "$(dirname "$0")/pw-metadata"
+
+KEYS="clock.force-quantum clock.force-rate clock.allowed-rates clock.min-quantum clock.max-quantum"
+
+settings() {
+    pw-metadata -n settings "$@"
+}
+
+# @returns the value of key in the settings metadata, nothing if it is not set
+value_of() {
+    settings 0 "$1" | sed -n "s/^update: id:0 key:'$1' value:'\(.*\)' type:'.*'$/\1/p"
+}
+
+set_or_delete() {
+    if [ -n "$2" ]; then
+        settings 0 "$1" "$2" > /dev/null
+    else
+        settings -d 0 "$1" > /dev/null
+    fi
+}
+
+# monitors the settings metadata into file while running the given commands
+monitor() {
+    file=$1
+    shift
+    settings -m > "$file" &
+    pid=$!
+    sleep 1
+    for change in "$@"; do
+        eval "$change" > /dev/null
+        sleep 0.2
+    done
+    sleep 1
+    kill "$pid"
+    wait "$pid" || true
+}
+
+saved=""
+for key in $KEYS; do
+    saved="$saved$key=$(value_of "$key")
+"
+done
+restore() {
+    printf '%s' "$saved" | while IFS='=' read -r key value; do
+        set_or_delete "$key" "$value"
+    done
+}
+trap restore EXIT
+
+settings 0 clock.min-quantum 32 > /dev/null
+settings 0 clock.max-quantum 2048 > /dev/null
+settings 0 clock.allowed-rates '[ 44100 48000 ]' > /dev/null
+settings 0 clock.force-rate 0 > /dev/null
+settings 0 clock.force-quantum 128 > /dev/null
+settings > settings_dump.txt
+settings 0 clock.force-quantum > force_quantum.txt
+
+settings -d 0 clock.force-rate > /dev/null
+settings 0 clock.force-rate > key_unset.txt
+
+pw-metadata -n default > default_json.txt
+
+monitor monitor.txt \
+    "settings 0 clock.force-quantum 0" \
+    "settings 0 clock.force-rate 0" \
+    "settings 0 clock.force-quantum 256" \
+    "settings 0 clock.force-rate 44100" \
+    "settings -d 0 clock.force-rate" \
+    "settings 0 clock.force-quantum 64"
+
+if [ "${1:-}" = "--with-remove-all" ]; then
+    monitor remove_all.txt \
+        "settings 0 clock.force-quantum 256" \
+        "settings 0 clock.force-rate 44100" \
+        "settings -d 0" \
+        "settings 0 clock.force-rate 96000"
+fi
+
+echo "captured with $(pw-metadata --version | tail -n 1), check git diff before committing"